@@ -0,0 +1,82 @@
+/**
+ * File: src/bin/tunnel-dns/main.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+// Authoritative responder for the dns tunnel api, the protocol is in the responder module.
+
+extern crate base64;
+extern crate openssl;
+
+#[macro_use]
+extern crate clap;
+
+#[macro_use]
+extern crate log;
+extern crate log4rs;
+
+mod responder;
+
+use std::net::UdpSocket;
+
+use responder::{Responder, parse_query, response};
+
+fn main() {
+    use clap::{App,Arg};
+    let matches = App::new("tunnel-dns")
+        .version(crate_version!())
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Authoritative dns responder keeping the tunnel files in memory, for the dns tunnel api.")
+        .arg(Arg::with_name("log-config")
+             .long("log-config")
+             .help("Log configuration file.")
+             .takes_value(true))
+        .arg(Arg::with_name("bind")
+             .long("bind")
+             .help("Address to listen on.")
+             .takes_value(true)
+             .default_value("0.0.0.0:53"))
+        .arg(Arg::with_name("domain")
+             .long("domain")
+             .help("Domain delegated to the responder, e.g. t.example.com")
+             .takes_value(true)
+             .required(true))
+        .arg(Arg::with_name("secret")
+             .long("secret")
+             .help("Shared secret of the tunnel, the same as in the dns section of its config.")
+             .takes_value(true)
+             .required(true))
+        .get_matches();
+    if let Some(log_config) = matches.value_of("log-config") {
+        log4rs::init_file(log_config, Default::default()).unwrap();
+    }
+    let domain = matches.value_of("domain").unwrap().trim_matches('.').to_lowercase();
+    let mut responder = Responder::new(&domain, matches.value_of("secret").unwrap());
+    let bind = matches.value_of("bind").unwrap();
+    let socket = UdpSocket::bind(bind).unwrap();
+    println!("Dns responder for {} is listening on {}", domain, bind);
+    let mut buffer = [0; 4096];
+    loop {
+        let (size, from) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(e) => {
+                error!("Failed to receive: {}", e);
+                continue;
+            }
+        };
+        let query = match parse_query(&buffer[..size]) {
+            Some(query) => query,
+            None => {
+                debug!("Invalid query from {}", from);
+                continue;
+            }
+        };
+        let (rcode, strings) = responder.handle(&query);
+        if let Err(e) = socket.send_to(&response(&query, rcode, strings), from) {
+            error!("Failed to answer {}: {}", from, e);
+        }
+    }
+}
@@ -0,0 +1,301 @@
+/**
+ * File: src/bin/tunnel-dns/responder.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+// Authoritative responder for the dns tunnel api, it keeps the tunnel files in memory and every
+// operation is a TXT query `<fields>.<command>.<nonce>.<mac>.<domain>`. The mac is the base32 of
+// the first 10 bytes of the HMAC-SHA256 of `<fields>.<command>.<nonce>` with the shared secret,
+// the queries without the right one are refused. The names and the written data
+// are lowercase base32, split to the labels of 63 characters, the numbers are decimal:
+// `<data>.<offset>.<transfer>.p` uploads a chunk of the data,
+// `<name>.<size>.<transfer>.c` stores the uploaded chunks as the object,
+// `<name>.<version>.<len>.g` reads the object from the start, unless it still has the version
+// (`none` for a missing object), `<name>.<version>.<offset>.<len>.r` reads the rest of it,
+// `<name>.d` removes it and `<prefix>.<offset>.l` lists the objects (`-` is an empty prefix).
+// The answers are the TXT strings: `ok`, `same`, `none`, `gone` when the read object has
+// changed, `<version> <size>` followed by the base64 data, or the count of the listed objects
+// followed by `<modified> <name>` of as many as fits into the answer.
+// The module is also a part of the tests of the dns tunnel api.
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use base64;
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// Longest dns message over udp, without EDNS.
+const MAX_MESSAGE: usize = 512;
+
+/// How long the unfinished uploads are kept, in seconds.
+const UPLOAD_AGE: u64 = 120;
+
+const TXT: u16 = 16;
+
+const FORMERR: u8 = 1;
+const NXDOMAIN: u8 = 3;
+const REFUSED: u8 = 5;
+
+const BASE32: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Bytes of the HMAC in the mac label.
+const MAC_LEN: usize = 10;
+
+/// One stored object.
+struct Stored {
+    data: Vec<u8>,
+    version: u64,
+    /// Seconds since the unix epoch.
+    modified: u64,
+}
+
+/// Chunks of the data uploaded by the queries, until they are stored as the object.
+struct Upload {
+    chunks: BTreeMap<usize, Vec<u8>>,
+    started: Instant,
+    /// The commit can be repeated, when its answer is lost, then it is only confirmed.
+    committed: bool,
+}
+
+pub struct Query {
+    /// Header of the query.
+    header: [u8; 4],
+    /// Lowercase labels of the name.
+    labels: Vec<String>,
+    kind: u16,
+    /// Question as it was sent, the resolvers check it in the answer.
+    question: Vec<u8>,
+}
+
+pub struct Responder {
+    /// Labels of the domain.
+    domain: Vec<String>,
+    /// Key of the HMAC of the query names.
+    secret: Vec<u8>,
+    objects: HashMap<String, Stored>,
+    /// Last version given to an object, it grows with every write.
+    last_version: u64,
+    uploads: HashMap<String, Upload>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+pub fn from_base32(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = BASE32.iter().position(|&b| b == c)? as u32;
+        buffer = (buffer << 5 | value) & 0xffff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// The mac label is the HMAC of the labels before it.
+fn is_authentic(secret: &[u8], labels: &[String], mac: &str) -> bool {
+    let key = PKey::hmac(secret).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(labels.join(".").as_bytes()).unwrap();
+    match from_base32(mac) {
+        Some(ref mac) if mac.len() == MAC_LEN => memcmp::eq(mac, &signer.finish().unwrap()[..MAC_LEN]),
+        _ => false,
+    }
+}
+
+pub fn parse_query(packet: &[u8]) -> Option<Query> {
+    // only a standard query with one question
+    if packet.len() < 12 || packet[2] & 0xf8 != 0 || packet[4..6] != [0, 1] {
+        return None;
+    }
+    let mut labels = Vec::new();
+    let mut pos = 12;
+    loop {
+        let len = *packet.get(pos)? as usize;
+        pos += 1;
+        if len == 0 {
+            break;
+        }
+        if len > 63 {
+            return None;
+        }
+        labels.push(String::from_utf8_lossy(packet.get(pos..pos + len)?).to_lowercase());
+        pos += len;
+    }
+    let kind = packet.get(pos..pos + 2).map(|b| (b[0] as u16) << 8 | b[1] as u16)?;
+    let question = packet.get(12..pos + 4)?.to_vec();
+    Some(Query {
+        header: [packet[0], packet[1], packet[2], packet[3]],
+        labels,
+        kind,
+        question,
+    })
+}
+
+/// Authoritative answer to the query, with the TXT record of the strings.
+pub fn response(query: &Query, rcode: u8, strings: Option<Vec<Vec<u8>>>) -> Vec<u8> {
+    let answers = if strings.is_some() { 1 } else { 0 };
+    // the opcode and the recursion desired are kept
+    let mut packet = vec![query.header[0], query.header[1], 0x80 | 0x04 | (query.header[2] & 0x79), rcode, 0, 1, 0, answers, 0, 0, 0, 0];
+    packet.extend_from_slice(&query.question);
+    if let Some(strings) = strings {
+        let rdata = strings.iter()
+            .flat_map(|string| Some(string.len() as u8).into_iter().chain(string.iter().cloned()))
+            .collect::<Vec<_>>();
+        packet.extend_from_slice(&[0xc0, 12, (TXT >> 8) as u8, TXT as u8, 0, 1, 0, 0, 0, 0, (rdata.len() >> 8) as u8, rdata.len() as u8]);
+        packet.extend(rdata);
+    }
+    packet
+}
+
+/// Room for the TXT strings in the answer to the query.
+fn room(query: &Query) -> usize {
+    MAX_MESSAGE.saturating_sub(12 + query.question.len() + 12)
+}
+
+/// The `<version> <size>` string and the data from the offset, as much as asked and fits.
+fn read_answer(query: &Query, stored: &Stored, offset: usize, len: usize) -> Vec<String> {
+    let head = format!("{} {}", stored.version, stored.data.len());
+    let room = room(query).saturating_sub(1 + head.len());
+    let chars = (room - room.div_ceil(256)) / 4 * 4;
+    let end = stored.data.len().min(offset.saturating_add(len)).min(offset + chars / 4 * 3);
+    let data = base64::encode(&stored.data[offset.min(end)..end]);
+    let mut strings = vec![head];
+    strings.extend(data.as_bytes().chunks(255).map(|string| String::from_utf8_lossy(string).into_owned()));
+    strings
+}
+
+impl Responder {
+    /// Responder for the domain without any objects, answering the queries with the secret.
+    pub fn new(domain: &str, secret: &str) -> Responder {
+        Responder {
+            domain: domain.split('.').map(|label| label.to_owned()).collect(),
+            secret: secret.as_bytes().to_vec(),
+            objects: HashMap::new(),
+            last_version: 0,
+            uploads: HashMap::new(),
+        }
+    }
+
+    /// Returns the rcode and the strings of the answer.
+    pub fn handle(&mut self, query: &Query) -> (u8, Option<Vec<Vec<u8>>>) {
+        if query.labels.len() < self.domain.len() + 3 || query.labels[query.labels.len() - self.domain.len()..] != self.domain[..] {
+            return (REFUSED, None);
+        }
+        let signed = &query.labels[..query.labels.len() - self.domain.len() - 1];
+        if !is_authentic(&self.secret, signed, &query.labels[signed.len()]) {
+            debug!("Refused query {}", query.labels.join("."));
+            return (REFUSED, None);
+        }
+        if query.kind != TXT {
+            return (0, None);
+        }
+        let fields = &signed[..signed.len() - 2];
+        let command = signed[signed.len() - 2].clone();
+        // the first fields are one base32 text in more labels, the numbers are at the end
+        let split = |numbers: usize| -> Option<(Vec<u8>, &[String])> {
+            if fields.len() <= numbers {
+                return None;
+            }
+            let (text, numbers) = fields.split_at(fields.len() - numbers);
+            Some((from_base32(&text.concat())?, numbers))
+        };
+        let text = |bytes: Vec<u8>| String::from_utf8(bytes).ok();
+        let number = |field: &String| field.parse::<usize>().ok();
+        let strings = match command.as_str() {
+            "p" => split(2).and_then(|(data, numbers)| Some(self.upload(data, number(&numbers[0])?, &numbers[1]))),
+            "c" => split(2).and_then(|(name, numbers)| self.commit(text(name)?, number(&numbers[0])?, &numbers[1])),
+            "g" => split(2).and_then(|(name, numbers)| Some(self.read(query, &text(name)?, &numbers[0], None, number(&numbers[1])?))),
+            "r" => split(3).and_then(|(name, numbers)| Some(self.read(query, &text(name)?, &numbers[0], number(&numbers[1]), number(&numbers[2])?))),
+            "d" => split(0).and_then(|(name, _)| Some(self.delete(&text(name)?))),
+            "l" if fields.len() == 2 && fields[0] == "-" => number(&fields[1]).map(|offset| self.list(query, "", offset)),
+            "l" => split(1).and_then(|(prefix, numbers)| Some(self.list(query, &text(prefix)?, number(&numbers[0])?))),
+            _ => return (NXDOMAIN, None),
+        };
+        match strings {
+            Some(strings) => (0, Some(strings.into_iter().map(|string| string.into_bytes()).collect())),
+            None => (FORMERR, None),
+        }
+    }
+
+    fn upload(&mut self, data: Vec<u8>, offset: usize, transfer: &str) -> Vec<String> {
+        self.uploads.retain(|_, upload| upload.started.elapsed() < Duration::from_secs(UPLOAD_AGE));
+        self.uploads.entry(transfer.to_owned())
+            .or_insert_with(|| Upload { chunks: BTreeMap::new(), started: Instant::now(), committed: false })
+            .chunks
+            .insert(offset, data);
+        vec!["ok".to_owned()]
+    }
+
+    fn commit(&mut self, name: String, size: usize, transfer: &str) -> Option<Vec<String>> {
+        let upload = self.uploads.entry(transfer.to_owned())
+            .or_insert_with(|| Upload { chunks: BTreeMap::new(), started: Instant::now(), committed: false });
+        if upload.committed {
+            return Some(vec!["ok".to_owned()]);
+        }
+        let mut data = Vec::with_capacity(size);
+        for (&offset, chunk) in &upload.chunks {
+            if offset != data.len() {
+                break;
+            }
+            data.extend_from_slice(chunk);
+        }
+        if data.len() != size {
+            warn!("Incomplete upload {} of {}, {} bytes of {}", transfer, name, data.len(), size);
+            return Some(vec!["incomplete".to_owned()]);
+        }
+        upload.committed = true;
+        upload.chunks.clear();
+        self.last_version += 1;
+        info!("PUT {} version {}, size = {}", name, self.last_version, size);
+        self.objects.insert(name, Stored { data, version: self.last_version, modified: now_secs() });
+        Some(vec!["ok".to_owned()])
+    }
+
+    /// Read from the start, unless the object still has the known version, or read the rest of
+    /// the object with the version.
+    fn read(&self, query: &Query, name: &str, version: &str, offset: Option<usize>, len: usize) -> Vec<String> {
+        let stored = self.objects.get(name);
+        let current = stored.map(|stored| stored.version.to_string()).unwrap_or_else(|| "none".to_owned());
+        match (stored, offset) {
+            (Some(stored), Some(offset)) if current == version => read_answer(query, stored, offset, len),
+            (_, Some(_)) => vec!["gone".to_owned()],
+            _ if current == version => vec!["same".to_owned()],
+            (Some(stored), None) => read_answer(query, stored, 0, len),
+            (None, None) => vec!["none".to_owned()],
+        }
+    }
+
+    fn delete(&mut self, name: &str) -> Vec<String> {
+        if self.objects.remove(name).is_some() {
+            info!("DELETE {}", name);
+        }
+        vec!["ok".to_owned()]
+    }
+
+    fn list(&self, query: &Query, prefix: &str, offset: usize) -> Vec<String> {
+        let mut names = self.objects.keys().filter(|name| name.starts_with(prefix)).collect::<Vec<_>>();
+        names.sort();
+        let mut strings = vec![names.len().to_string()];
+        let mut room = room(query).saturating_sub(1 + strings[0].len());
+        for name in names.into_iter().skip(offset) {
+            let entry = format!("{} {}", self.objects[name].modified, name);
+            if entry.len() > 255 || entry.len() + 1 > room {
+                break;
+            }
+            room -= entry.len() + 1;
+            strings.push(entry);
+        }
+        strings
+    }
+}
@@ -0,0 +1,301 @@
+/**
+ * File: src/bin/tunnel-relay.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+// Relay for the relay tunnel api, it keeps the tunnel files in memory and serves them over http:
+// `PUT /objects/<name>` stores the body, `DELETE /objects/<name>` removes it and
+// `GET /objects/<name>?version=<version>&wait=<seconds>` returns it with its version in the ETag.
+// The GET waits up to `wait` seconds while the version of the object is still `version` (`none`
+// for a missing object), then it is 304. `GET /list/<prefix>` prints `<modified> <name>` for
+// every object starting with the prefix.
+
+extern crate hyper;
+
+#[macro_use]
+extern crate clap;
+
+#[macro_use]
+extern crate log;
+extern crate log4rs;
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::ToSocketAddrs;
+use std::sync::{Mutex, Condvar};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use hyper::header::{Authorization, Bearer, ETag, EntityTag};
+use hyper::server::{Server, Handler, Request, Response};
+use hyper::uri::RequestUri;
+
+/// The longest wait of one GET, so a dead client doesn't block the thread for long.
+const MAX_WAIT: u64 = 60;
+
+/// One stored object.
+struct Stored {
+    data: Vec<u8>,
+    version: u64,
+    /// Seconds since the unix epoch.
+    modified: u64,
+}
+
+struct Objects {
+    objects: HashMap<String, Stored>,
+    /// Last version given to an object, it grows with every write.
+    last_version: u64,
+}
+
+struct Relay {
+    objects: Mutex<Objects>,
+    /// Notified on every change of any object.
+    changed: Condvar,
+    /// Token the clients have to send, if it is set.
+    token: Option<String>,
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn version_of(objects: &Objects, name: &str) -> String {
+    objects.objects.get(name)
+        .map(|stored| stored.version.to_string())
+        .unwrap_or_else(|| "none".to_owned())
+}
+
+/// Only the clients on this machine can reach the address.
+fn is_loopback(bind: &str) -> bool {
+    match bind.to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs = addrs.collect::<Vec<_>>();
+            !addrs.is_empty() && addrs.iter().all(|addr| addr.ip().is_loopback())
+        }
+        Err(_) => false,
+    }
+}
+
+impl Relay {
+    fn new(token: Option<String>) -> Relay {
+        Relay {
+            objects: Mutex::new(Objects { objects: HashMap::new(), last_version: 0 }),
+            changed: Condvar::new(),
+            token,
+        }
+    }
+
+    fn put(&self, name: &str, data: Vec<u8>) -> (StatusCode, Vec<u8>) {
+        let mut objects = self.objects.lock().unwrap();
+        objects.last_version += 1;
+        let version = objects.last_version;
+        info!("PUT {} version {}, size = {}", name, version, data.len());
+        objects.objects.insert(name.to_owned(), Stored { data, version, modified: now_secs() });
+        self.changed.notify_all();
+        (StatusCode::NoContent, Vec::new())
+    }
+
+    fn delete(&self, name: &str) -> (StatusCode, Vec<u8>) {
+        let mut objects = self.objects.lock().unwrap();
+        info!("DELETE {}", name);
+        if objects.objects.remove(name).is_some() {
+            self.changed.notify_all();
+            (StatusCode::NoContent, Vec::new())
+        } else {
+            (StatusCode::NotFound, Vec::new())
+        }
+    }
+
+    /// Returns the status, the version and the body.
+    fn get(&self, name: &str, version: Option<&str>, wait: u64) -> (StatusCode, Option<String>, Vec<u8>) {
+        let deadline = Instant::now() + Duration::from_secs(wait.min(MAX_WAIT));
+        let mut objects = self.objects.lock().unwrap();
+        while version == Some(&version_of(&objects, name)) {
+            let now = Instant::now();
+            if now >= deadline {
+                return (StatusCode::NotModified, version.map(|v| v.to_owned()), Vec::new());
+            }
+            objects = self.changed.wait_timeout(objects, deadline - now).unwrap().0;
+        }
+        match objects.objects.get(name) {
+            Some(stored) => (StatusCode::Ok, Some(stored.version.to_string()), stored.data.clone()),
+            None => (StatusCode::NotFound, None, Vec::new()),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> (StatusCode, Vec<u8>) {
+        let objects = self.objects.lock().unwrap();
+        let mut list = String::new();
+        for (name, stored) in objects.objects.iter().filter(|&(name, _)| name.starts_with(prefix)) {
+            list.push_str(&format!("{} {}\n", stored.modified, name));
+        }
+        (StatusCode::Ok, list.into_bytes())
+    }
+}
+
+impl Handler for Relay {
+    fn handle(&self, mut req: Request, mut res: Response) {
+        let path = match req.uri {
+            RequestUri::AbsolutePath(ref path) => path.clone(),
+            _ => String::new(),
+        };
+        let (path, query) = match path.find('?') {
+            Some(pos) => (path[..pos].to_owned(), path[pos + 1..].to_owned()),
+            None => (path, String::new()),
+        };
+        let param = |key: &str| query.split('&')
+            .filter_map(|pair| {
+                let mut pair = pair.splitn(2, '=');
+                match (pair.next(), pair.next()) {
+                    (Some(k), Some(v)) if k == key => Some(v.to_owned()),
+                    _ => None,
+                }
+            })
+            .next();
+
+        let authorized = match self.token {
+            Some(ref token) => req.headers.get::<Authorization<Bearer>>().map(|auth| &auth.0.token == token).unwrap_or(false),
+            None => true,
+        };
+        let (status, version, body) = if !authorized {
+            (StatusCode::Unauthorized, None, Vec::new())
+        } else if let Some(name) = path.strip_prefix("/objects/") {
+            match req.method {
+                Method::Put => {
+                    let mut data = Vec::new();
+                    match req.read_to_end(&mut data) {
+                        Ok(_) => {
+                            let (status, body) = self.put(name, data);
+                            (status, None, body)
+                        }
+                        Err(e) => {
+                            error!("Failed to read {}: {}", name, e);
+                            (StatusCode::BadRequest, None, Vec::new())
+                        }
+                    }
+                }
+                Method::Delete => {
+                    let (status, body) = self.delete(name);
+                    (status, None, body)
+                }
+                Method::Get => {
+                    let wait = param("wait").and_then(|wait| wait.parse().ok()).unwrap_or(0);
+                    self.get(name, param("version").as_deref(), wait)
+                }
+                _ => (StatusCode::MethodNotAllowed, None, Vec::new()),
+            }
+        } else if let (Some(prefix), Method::Get) = (path.strip_prefix("/list/"), &req.method) {
+            let (status, body) = self.list(prefix);
+            (status, None, body)
+        } else {
+            (StatusCode::NotFound, None, Vec::new())
+        };
+
+        *res.status_mut() = status;
+        if let Some(version) = version {
+            res.headers_mut().set(ETag(EntityTag::new(false, version)));
+        }
+        if let Err(e) = res.send(&body) {
+            error!("Failed to send the response: {}", e);
+        }
+    }
+}
+
+fn main() {
+    use clap::{App,Arg};
+    let matches = App::new("tunnel-relay")
+        .version(crate_version!())
+        .author(env!("CARGO_PKG_AUTHORS"))
+        .about("Relay keeping the tunnel files in memory, for the relay tunnel api.")
+        .arg(Arg::with_name("log-config")
+             .long("log-config")
+             .help("Log configuration file.")
+             .takes_value(true))
+        .arg(Arg::with_name("bind")
+             .long("bind")
+             .help("Address to listen on, other than the loopback needs the token.")
+             .takes_value(true)
+             .default_value("127.0.0.1:8080"))
+        .arg(Arg::with_name("threads")
+             .long("threads")
+             .help("Number of the threads serving the requests, every tunnel endpoint keeps one busy with the waiting read.")
+             .takes_value(true)
+             .default_value("64")
+             .validator(|val| val.parse::<usize>().map(|_| ()).map_err(|_| format!("Cannot parse {} to usize", val))))
+        .arg(Arg::with_name("token")
+             .long("token")
+             .help("Token the clients have to send, it can be left out only on the loopback address.")
+             .takes_value(true))
+        .get_matches();
+    if let Some(log_config) = matches.value_of("log-config") {
+        log4rs::init_file(log_config, Default::default()).unwrap();
+    }
+    let bind = matches.value_of("bind").unwrap();
+    let token = matches.value_of("token").map(|token| token.to_owned());
+    // anybody who can reach the relay could read and write the tunnels
+    if token.is_none() && !is_loopback(bind) {
+        clap::Error::with_description(&format!("--token is required to listen on {}", bind), clap::ErrorKind::MissingRequiredArgument).exit();
+    }
+    let relay = Relay::new(token);
+    let threads = value_t!(matches, "threads", usize).unwrap();
+    let _listening = Server::http(bind)
+        .and_then(|server| server.handle_threads(relay, threads))
+        .unwrap();
+    println!("Relay is listening on {}", bind);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn loopback_binds() {
+        assert!(is_loopback("127.0.0.1:8080"));
+        assert!(is_loopback("[::1]:8080"));
+        assert!(!is_loopback("0.0.0.0:8080"));
+        assert!(!is_loopback("192.168.1.1:8080"));
+        assert!(!is_loopback("no port"));
+    }
+
+    #[test]
+    fn get_returns_new_versions() {
+        let relay = Relay::new(None);
+        assert_eq!(relay.get("a", None, 0), (StatusCode::NotFound, None, Vec::new()));
+        relay.put("a", b"one".to_vec());
+        relay.put("b", b"other".to_vec());
+        assert_eq!(relay.get("a", None, 0), (StatusCode::Ok, Some("1".to_owned()), b"one".to_vec()));
+        // the version of the other side doesn't matter, only whether it is the current one
+        assert_eq!(relay.get("a", Some("none"), 10), (StatusCode::Ok, Some("1".to_owned()), b"one".to_vec()));
+        assert_eq!(relay.get("b", Some("1"), 10), (StatusCode::Ok, Some("2".to_owned()), b"other".to_vec()));
+    }
+
+    #[test]
+    fn get_waits_for_change() {
+        let relay = Arc::new(Relay::new(None));
+        let start = Instant::now();
+        assert_eq!(relay.get("a", Some("none"), 1), (StatusCode::NotModified, Some("none".to_owned()), Vec::new()));
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        let writer = relay.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            // other objects don't end the wait
+            writer.put("b", b"other".to_vec());
+            thread::sleep(Duration::from_millis(200));
+            writer.put("a", b"one".to_vec());
+            thread::sleep(Duration::from_millis(200));
+            writer.delete("a");
+        });
+        let start = Instant::now();
+        assert_eq!(relay.get("a", Some("none"), 10), (StatusCode::Ok, Some("2".to_owned()), b"one".to_vec()));
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert_eq!(relay.get("a", Some("2"), 10), (StatusCode::NotFound, None, Vec::new()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -2,36 +2,128 @@
  * File: src/client.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 07.09.2017
- * Last Modified Date: 11.10.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 
-use tunnel::Tunnel;
+use tunnel::{self, Tunnel, Keepalive};
 use std::io::{self};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
 use clap::ArgMatches;
-use connection::{manage_clients, run_connection};
+use session::{self, Session};
+use storage::{self, Storage, Periodic};
+use connection::{manage_clients, connect};
+use forward::{Forwards, REVERSE_ID_BASE};
+use udp::{manage_flows, new_flows};
+use std::thread;
+use std::sync::{Arc, Mutex};
+use shutdown;
 
+/// How often to look for new sessions.
+const WATCH_INTERVAL: u64 = 5;
 
 pub fn run(_matches: &ArgMatches, tunnel: Tunnel) -> io::Result<()>{
-    use std::net::TcpStream;
+    serve(tunnel)
+}
+
+/// Serve the connections of one tunnel, it returns when the tunnel is closed.
+fn serve(tunnel: Tunnel) -> io::Result<()> {
     let tunnel_reader = tunnel.reader;
     let tunnel_writer = tunnel.writer;
-    let tunnel_connection = tunnel.connection.unwrap();
-
-    let client_state_sender = manage_clients(tunnel_reader);
-
-
-    for (id, ip, port) in tunnel_connection.iter() {
-        info!("Got connection [{}] to {}:{}", id, ip, port);
-        let address = format!("{}:{}",ip, port); // create connection to the ssh
-        let tunnel_writer = tunnel_writer.clone();
-        let client_state_sender = client_state_sender.clone();
-        TcpStream::connect(&address)
-            .and_then(move |socket| {
-                run_connection( tunnel_writer, id, client_state_sender, socket);
-                Ok(())
-            })
-            .unwrap();
-    }
+    let tunnel_connection = tunnel.connection;
+    let tunnel_listen = tunnel.listen;
+
+    let client_state_sender = manage_clients(tunnel_reader, tunnel.peer.clone(), tunnel.restarted);
+    let flows = new_flows();
+    // the destinations are from the forward rules of the server side
+    manage_flows(tunnel.datagram, tunnel_writer.clone(), flows.clone(), REVERSE_ID_BASE, |_, _| true);
+
+    // reverse rules, the server asks to listen here and connections are made on the server side
+    let forwards = Forwards::new(REVERSE_ID_BASE, tunnel_writer.clone(), client_state_sender.clone(), flows.clone(), tunnel.peer.clone());
+    let forwards = Arc::new(Mutex::new(forwards));
+    let listen_forwards = forwards.clone();
+    thread::spawn(move || {
+        for rules in tunnel_listen.iter() {
+            listen_forwards.lock().unwrap().update(rules);
+        }
+    });
+
+    let connection_writer = tunnel_writer.clone();
+    let connection_state_sender = client_state_sender.clone();
+    thread::spawn(move || {
+        for (id, endpoint) in tunnel_connection.iter() {
+            info!("Got connection [{}] to {}", id, endpoint);
+            connect(connection_writer.clone(), id, connection_state_sender.clone(), &endpoint);
+        }
+    });
+
+    shutdown::wait();
+    shutdown::close(&mut forwards.lock().unwrap(), &client_state_sender, &tunnel_writer);
     Ok(())
 }
+
+/// Watch the bucket for the sessions with a running server and serve every one of them with its
+/// own tunnel. Every tunnel gets its own storage connection. The tunnel of a session runs until
+/// the shutdown, also while its server is stopped, and it takes over the restarted server. When
+/// the tunnel fails, the session is served again with a new one, once it is active. The heartbeat
+/// is written by the watcher, not by the tunnels.
+pub fn run_sessions<F>(file_name: &str, keepalive: Keepalive, mut heartbeat: Periodic, new_storage: F) -> io::Result<()>
+    where F: Fn() -> io::Result<Box<dyn Storage>>
+{
+    let mut watcher = new_storage()?;
+    let mut served = HashSet::new();
+    let mut threads: Vec<(String, thread::JoinHandle<()>)> = Vec::new();
+    let mut last_heartbeat: Option<Instant> = None;
+    loop {
+        if last_heartbeat.map(|last| last.elapsed() >= heartbeat.interval).unwrap_or(true) {
+            if let Err(e) = watcher.put(&heartbeat.name, &(heartbeat.content)()) {
+                error!("Failed to write {}: {}", heartbeat.name, e);
+            }
+            last_heartbeat = Some(Instant::now());
+        }
+        // the thread of the session ends, when its tunnel fails or panics
+        threads.retain(|(name, thread)| {
+            let finished = thread.is_finished();
+            if finished {
+                served.remove(name);
+            }
+            !finished
+        });
+        match session::active(&mut *watcher, file_name) {
+            Ok(sessions) => {
+                for name in sessions {
+                    if served.contains(&name) {
+                        continue;
+                    }
+                    info!("Serving new session '{}'", name);
+                    let session = Session::new(Some(&name), file_name);
+                    let started = new_storage()
+                        .and_then(|storage| storage::create_pipes(false, storage, &session.client_to_server(), &session.server_to_client(), Vec::new()))
+                        .and_then(|pipes| tunnel::run(pipes, keepalive));
+                    match started {
+                        Ok(tunnel) => {
+                            let session_name = name.clone();
+                            threads.push((name.clone(), thread::spawn(move || {
+                                if let Err(e) = serve(tunnel) {
+                                    error!("Session '{}' failed: {}", session_name, e);
+                                }
+                            })));
+                            served.insert(name);
+                        }
+                        Err(e) => error!("Failed to start the tunnel for session '{}': {}", name, e),
+                    }
+                }
+            }
+            Err(e) => error!("Failed to list the sessions: {}", e),
+        }
+        if shutdown::sleep(Duration::from_secs(WATCH_INTERVAL)) {
+            break;
+        }
+    }
+    // every session shuts down its own tunnel
+    for (_, thread) in threads {
+        let _ = thread.join();
+    }
+    watcher.delete(&heartbeat.name)
+}
@@ -0,0 +1,114 @@
+/**
+ * File: src/command.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self};
+use config::CommandConfig;
+
+use storage::{Storage, Object};
+use tools::{parse_time, wait_with_input};
+
+/// Quote the value for the shell.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Storage using the shell commands from the config. In the templates `{name}` is replaced by the
+/// name of the object and `{prefix}` by the listed prefix. The object is passed through stdin of
+/// the put command and stdout of the get command.
+pub struct CommandStorage {
+    cfg: CommandConfig,
+}
+
+impl CommandStorage {
+    /// Run the command, the exit codes from `not_found` are NotFound error, any other failure is
+    /// just logged by the caller and the operation is tried again next time.
+    fn run(&self, template: &str, value: &str, input: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        use std::process::{Command, Stdio};
+        // the value is the object name or the listed prefix, depending on the command
+        let command = template
+            .replace("{name}", &quote(value))
+            .replace("{prefix}", &quote(value));
+        info!("{}", command);
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let output = wait_with_input(child, input)?;
+        match output.status.code() {
+            Some(0) => Ok(output.stdout),
+            Some(code) if self.cfg.not_found.contains(&code) => Err(io::Error::new(io::ErrorKind::NotFound, "object not found")),
+            code => Err(io::Error::new(io::ErrorKind::Other,
+                    format!("'{}' failed ({:?}): {}", command, code, String::from_utf8_lossy(&output.stderr).trim()))),
+        }
+    }
+}
+
+impl Storage for CommandStorage {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.run(&self.cfg.put, name, Some(data))
+            .map(|_| ())
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.run(&self.cfg.get, name, None) {
+            Ok(object) => Ok(Some(object)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        match self.run(&self.cfg.delete, name, None) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// Every line of the output is one object, the last word is its name, with `list_strip`
+    /// removed from the beginning. If the line starts with the date and time, like the output
+    /// of `s3cmd ls`, it is the modification time.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let template = match self.cfg.list {
+            Some(ref template) => template.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::Other, "the list command is not configured")),
+        };
+        let output = match self.run(&template, prefix, None) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            result => result?,
+        };
+        let strip = self.cfg.list_strip.clone().unwrap_or_default();
+        Ok(String::from_utf8_lossy(&output)
+            .lines()
+            .filter_map(|line| {
+                let parts = line.split_whitespace().collect::<Vec<_>>();
+                let name = parts.last()?;
+                if !name.starts_with(&strip) || !name[strip.len()..].starts_with(prefix) {
+                    return None;
+                }
+                let modified = if parts.len() >= 3 {
+                    parse_time(parts[0], parts[1])
+                } else {
+                    None
+                };
+                Some(Object {
+                    name: name[strip.len()..].to_owned(),
+                    modified,
+                })
+            })
+            .collect())
+    }
+}
+
+pub fn create_storage(cfg: CommandConfig) -> io::Result<Box<dyn Storage>> {
+    Ok(Box::new(CommandStorage {
+        cfg,
+    }))
+}
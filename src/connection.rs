@@ -2,23 +2,87 @@
  * File: src/connection.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 09.10.2017
- * Last Modified Date: 11.10.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 
 
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::net::TcpStream;
-use tunnel::{WriterData, ReaderData};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+use tunnel::{WriterData, ReaderData, PeerStatus};
+use messages::Endpoint;
 use std::thread;
 use std::io::{self,Read,Write};
 
+/// Stream of one connection.
+pub trait Stream: Read + Write + Send + 'static {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl Stream for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Tcp or unix stream, when the type is known only at runtime.
+pub enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Read for Socket {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut socket)  => socket.read(buf),
+            Socket::Unix(ref mut socket) => socket.read(buf),
+        }
+    }
+}
+
+impl Write for Socket {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Socket::Tcp(ref mut socket)  => socket.write(buf),
+            Socket::Unix(ref mut socket) => socket.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref mut socket)  => socket.flush(),
+            Socket::Unix(ref mut socket) => socket.flush(),
+        }
+    }
+}
+
+impl Stream for Socket {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match *self {
+            Socket::Tcp(ref socket)  => TcpStream::set_read_timeout(socket, timeout),
+            Socket::Unix(ref socket) => UnixStream::set_read_timeout(socket, timeout),
+        }
+    }
+}
+
 pub enum ClientState {
     NewClient(u64, Sender<ReaderData>),
     Disconnected(u64),
+    /// Disconnect all the clients, the sender is notified when they are all closed.
+    CloseAll(Sender<()>),
 }
 
-pub fn manage_clients(tunnel_reader: Receiver<(u64, ReaderData)>) -> Sender<ClientState> {
+/// Pass the data from the tunnel to the clients. When the other side of the tunnel dies or it is
+/// restarted, all the clients are disconnected.
+pub fn manage_clients(tunnel_reader: Receiver<(u64, ReaderData)>, peer: PeerStatus, restarted: Receiver<()>) -> Sender<ClientState> {
     let (client_state_sender, client_state_receiver) = channel();
 
     use std::thread;
@@ -26,14 +90,48 @@ pub fn manage_clients(tunnel_reader: Receiver<(u64, ReaderData)>) -> Sender<Clie
         // the data which come from the tunnel, needs to be redirected to the appropriate client. The
         // client is identified by the `id`
         use std::collections::HashMap;
-        let mut clients = HashMap::new();
+        use std::sync::atomic::Ordering;
+        let mut clients: HashMap<u64, Sender<ReaderData>> = HashMap::new();
+        let mut peer_alive = false;
+        let mut closing: Vec<Sender<()>> = Vec::new();
         loop {
+            // before the new clients, they can be already connected to the restarted side
+            if restarted.try_iter().count() > 0 {
+                for (id, sender) in clients.drain() {
+                    info!("[{}] Closing connection, the other side has been restarted", id);
+                    let _ = sender.send(ReaderData::Disconnect);
+                }
+            }
             for state in client_state_receiver.try_iter() {
                 match state {
-                    ClientState::NewClient(id, sender) => clients.insert(id, sender),
-                    ClientState::Disconnected(id)      => clients.remove(&id),
+                    ClientState::NewClient(id, sender) => {
+                        clients.insert(id, sender);
+                    }
+                    ClientState::Disconnected(id) => {
+                        clients.remove(&id);
+                    }
+                    ClientState::CloseAll(sender) => {
+                        for (id, client) in &clients {
+                            info!("[{}] Closing connection", id);
+                            let _ = client.send(ReaderData::Disconnect);
+                        }
+                        closing.push(sender);
+                    }
                 };
             }
+            if clients.is_empty() {
+                for sender in closing.drain(..) {
+                    let _ = sender.send(());
+                }
+            }
+            let alive = peer.load(Ordering::SeqCst);
+            if peer_alive && !alive {
+                for (id, sender) in clients.drain() {
+                    info!("[{}] Closing connection, the other side is dead", id);
+                    let _ = sender.send(ReaderData::Disconnect);
+                }
+            }
+            peer_alive = alive;
             for (id, data) in tunnel_reader.try_iter() {
                 match clients.get(&id) {
                     Some(sender)   => {
@@ -53,14 +151,13 @@ pub fn manage_clients(tunnel_reader: Receiver<(u64, ReaderData)>) -> Sender<Clie
 }
 
 
-pub fn run_connection(tunnel_writer: Sender<WriterData>, id: u64, client_state_sender: Sender<ClientState>, socket: TcpStream) {
+pub fn run_connection<S: Stream>(tunnel_writer: Sender<WriterData>, id: u64, client_state_sender: Sender<ClientState>, socket: S) {
     let mut socket = socket;
     thread::spawn(move|| {
         debug!("[{}] Thread started.", id);
         let (data_sender, data_receiver) = channel();
         client_state_sender.send(ClientState::NewClient(id, data_sender)).unwrap();
         let mut buf = [0;2048];
-        use std::time::Duration;
         socket
             .set_read_timeout(Some(Duration::from_millis(20)))
             .and_then(move |_| {
@@ -104,3 +201,19 @@ pub fn run_connection(tunnel_writer: Sender<WriterData>, id: u64, client_state_s
     });
 }
 
+
+/// Create a new connection requested by the other side of the tunnel. If the connection fails, the
+/// other side is told to disconnect.
+pub fn connect(tunnel_writer: Sender<WriterData>, id: u64, client_state_sender: Sender<ClientState>, endpoint: &Endpoint) {
+    let socket = match *endpoint {
+        Endpoint::Tcp(ref ip, port) => TcpStream::connect((ip.as_str(), port)).map(Socket::Tcp),
+        Endpoint::Unix(ref path)    => UnixStream::connect(path).map(Socket::Unix),
+    };
+    match socket {
+        Ok(socket) => run_connection(tunnel_writer, id, client_state_sender, socket),
+        Err(e) => {
+            error!("[{}] Failed to connect to {}: {}", id, endpoint, e);
+            tunnel_writer.send(WriterData::Disconnect(id)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,664 @@
+/**
+ * File: src/dns.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self};
+use std::net::{UdpSocket, ToSocketAddrs};
+use std::time::{Duration, Instant};
+use base64;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+use config::DnsConfig;
+use storage::{Storage, Object, Fetch};
+
+#[cfg(test)]
+#[path = "bin/tunnel-dns/responder.rs"]
+mod responder;
+
+/// Longest dns message over udp, without EDNS.
+const MAX_MESSAGE: usize = 512;
+
+/// Longest domain name and its label.
+const MAX_NAME: usize = 253;
+const MAX_LABEL: usize = 63;
+
+/// Labels of the query after the data: `.<offset>.<transfer>.p.<nonce>.<mac>.`
+const UPLOAD_FIELDS: usize = 49;
+
+/// Bytes of the HMAC in the mac label.
+const MAC_LEN: usize = 10;
+
+/// How many queries can wait for the answer at once.
+const WINDOW: usize = 16;
+
+/// How long to wait for the answer before the query is sent again, in milliseconds.
+const RETRY: u64 = 1000;
+
+/// How many times one query is sent, before the operation fails.
+const ATTEMPTS: u32 = 5;
+
+const TXT: u16 = 16;
+
+/// The names are case insensitive, so the data in them is in lowercase base32.
+const BASE32: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Object on the responder, as seen by the read.
+enum Remote {
+    /// It still has the version the reader knows.
+    Same,
+    Missing,
+    Object(String, Vec<u8>),
+}
+
+fn base32(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &b in data {
+        buffer = (buffer << 8 | b as u32) & 0xffff;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(BASE32[(buffer >> bits & 31) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(BASE32[(buffer << (5 - bits) & 31) as usize] as char);
+    }
+    encoded
+}
+
+/// Split the text to the labels of the name.
+fn labels(text: &str) -> String {
+    text.as_bytes()
+        .chunks(MAX_LABEL)
+        .map(|label| String::from_utf8_lossy(label).into_owned())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Label authenticating the rest of the query name, the HMAC-SHA256 of it with the shared
+/// secret, shortened to fit into the names.
+fn mac(secret: &str, text: &str) -> String {
+    let key = PKey::hmac(secret.as_bytes()).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    signer.update(text.as_bytes()).unwrap();
+    base32(&signer.finish().unwrap()[..MAC_LEN])
+}
+
+/// Random enough number for the nonces and the transfers.
+fn unique() -> u32 {
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{SystemTime, UNIX_EPOCH};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    (now.subsec_nanos() ^ (now.as_secs() as u32).rotate_left(16) ^ process::id().rotate_left(8))
+        .wrapping_add((NEXT.fetch_add(1, Ordering::SeqCst) as u32).wrapping_mul(2654435761))
+}
+
+/// TXT query of the name, the recursion is desired, so it can go through any resolver.
+fn query(id: u16, name: &str) -> Vec<u8> {
+    let mut packet = vec![(id >> 8) as u8, id as u8, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in name.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&[(TXT >> 8) as u8, TXT as u8, 0, 1]);
+    packet
+}
+
+/// The response has the id and the question of the query. The resolvers can change the case
+/// of the name.
+fn is_answer(response: &[u8], query: &[u8]) -> bool {
+    response.len() >= query.len()
+        && response[..2] == query[..2]
+        && response[2] & 0x80 != 0
+        && response[12..query.len()].eq_ignore_ascii_case(&query[12..])
+}
+
+/// Position after the name, which can be compressed.
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0xc0 == 0xc0 {
+            return Some(pos + 2);
+        }
+        pos += len + 1;
+    }
+}
+
+/// Strings of the first TXT record of the response.
+fn parse_answer(response: &[u8], query: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid dns response");
+    if response[2] & 0x02 != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated dns response"));
+    }
+    let rcode = response[3] & 0x0f;
+    if rcode != 0 {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("dns query failed with rcode {}", rcode)));
+    }
+    let u16_at = |pos: usize| response.get(pos..pos + 2).map(|b| (b[0] as u16) << 8 | b[1] as u16);
+    let answers = u16_at(6).ok_or_else(invalid)?;
+    let mut pos = query.len();
+    for _ in 0..answers {
+        pos = skip_name(response, pos).ok_or_else(invalid)?;
+        let kind = u16_at(pos).ok_or_else(invalid)?;
+        let len = u16_at(pos + 8).ok_or_else(invalid)? as usize;
+        let rdata = response.get(pos + 10..pos + 10 + len).ok_or_else(invalid)?;
+        pos += 10 + len;
+        if kind != TXT {
+            continue;
+        }
+        let mut strings = Vec::new();
+        let mut rest = rdata;
+        while let Some((&len, tail)) = rest.split_first() {
+            let string = tail.get(..len as usize).ok_or_else(invalid)?;
+            strings.push(string.to_vec());
+            rest = &tail[len as usize..];
+        }
+        return Ok(strings);
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidData, "no TXT record in the dns response"))
+}
+
+/// The answer is just `ok`.
+fn expect_ok(answer: &[Vec<u8>]) -> io::Result<()> {
+    match answer.first() {
+        Some(status) if status == b"ok" => Ok(()),
+        status => Err(io::Error::new(io::ErrorKind::Other, format!("tunnel-dns: {}",
+            status.map(|status| String::from_utf8_lossy(status).into_owned()).unwrap_or_default()))),
+    }
+}
+
+/// Data of the read answer, after its `<version> <size>` string.
+fn answer_data(answer: &[Vec<u8>]) -> io::Result<Vec<u8>> {
+    let text = answer.iter().skip(1).flat_map(|string| string.iter().cloned()).collect::<Vec<_>>();
+    io_res!(base64::decode(&text), InvalidData)
+}
+
+/// How many bytes of the object fit into the answer of the read query with the name. The answer
+/// has the question, the TXT record with `<version> <size>` and the base64 data in the strings
+/// of 255 bytes.
+fn read_len(name: &str) -> usize {
+    let room = MAX_MESSAGE - 12 - (name.len() + 2 + 4) - 12 - 42;
+    let chars = (room - room.div_ceil(256)) / 4 * 4;
+    chars / 4 * 3
+}
+
+/// Storage on the tunnel-dns responder, all the operations are TXT queries of its domain, so
+/// they can go through any resolver. The data of the writes are in the names of the queries,
+/// split to many of them, the responder puts them together. The reads get the object in the
+/// TXT records, again split to many queries when it is bigger. Every query has a nonce, so the
+/// resolvers don't answer from their cache, and the mac of its name, so the responder answers
+/// only the queries of the tunnel.
+pub struct DnsStorage {
+    cfg: DnsConfig,
+    socket: UdpSocket,
+    next_id: u16,
+    /// Bytes of the written data in one query.
+    chunk: usize,
+    /// When the watched files have been checked last time.
+    checked: Vec<(String, Instant)>,
+}
+
+impl DnsStorage {
+    /// Full name of the query with the labels.
+    fn name(&self, labels: &str) -> io::Result<String> {
+        let signed = format!("{}.{:08x}", labels, unique());
+        let name = format!("{}.{}.{}", signed, mac(&self.cfg.secret, &signed), self.cfg.domain);
+        if name.len() > MAX_NAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the dns name is too long: {}", name)));
+        }
+        Ok(name)
+    }
+
+    /// Names of the upload queries of the chunks of the data and of the commit query.
+    fn upload_names(&self, name: &str, data: &[u8], transfer: &str) -> io::Result<(Vec<String>, String)> {
+        let chunks = data.chunks(self.chunk)
+            .enumerate()
+            .map(|(i, chunk)| self.name(&format!("{}.{}.{}.p", labels(&base32(chunk)), i * self.chunk, transfer)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let commit = self.name(&format!("{}.{}.{}.c", labels(&base32(name.as_bytes())), data.len(), transfer))?;
+        Ok((chunks, commit))
+    }
+
+    /// Name of the read query from the offset, returns it and how many bytes it asks for.
+    fn read_name(&self, encoded: &str, fields: &str, command: &str) -> io::Result<(String, usize)> {
+        let len = read_len(&self.name(&format!("{}.{}.999.{}", encoded, fields, command))?);
+        Ok((self.name(&format!("{}.{}.{}.{}", encoded, fields, len, command))?, len))
+    }
+
+    /// Send all the queries, returns the strings of their answers. Only a window of them waits
+    /// for the answer at once, the lost ones are sent again.
+    fn query_all(&mut self, names: &[String]) -> io::Result<Vec<Vec<Vec<u8>>>> {
+        let mut answers = names.iter().map(|_| None).collect::<Vec<Option<Vec<Vec<u8>>>>>();
+        // index of the name, the query, when it was sent and how many times
+        let mut waiting: Vec<(usize, Vec<u8>, Instant, u32)> = Vec::new();
+        let (mut next, mut done) = (0, 0);
+        let mut buffer = [0; 4096];
+        while done < names.len() {
+            while waiting.len() < WINDOW && next < names.len() {
+                self.next_id = self.next_id.wrapping_add(1);
+                let packet = query(self.next_id, &names[next]);
+                self.socket.send(&packet)?;
+                waiting.push((next, packet, Instant::now(), 1));
+                next += 1;
+            }
+            match self.socket.recv(&mut buffer) {
+                Ok(size) => {
+                    let response = &buffer[..size];
+                    // late answers of the repeated queries are ignored
+                    if let Some(pos) = waiting.iter().position(|(_, query, _, _)| is_answer(response, query)) {
+                        let (index, query, _, _) = waiting.swap_remove(pos);
+                        answers[index] = Some(parse_answer(response, &query)?);
+                        done += 1;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => (),
+                Err(e) => return Err(e),
+            }
+            for &mut (index, ref query, ref mut sent, ref mut attempts) in waiting.iter_mut() {
+                if sent.elapsed() < Duration::from_millis(RETRY) {
+                    continue;
+                }
+                if *attempts >= ATTEMPTS {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, format!("no dns answer for {}", names[index])));
+                }
+                self.socket.send(query)?;
+                *sent = Instant::now();
+                *attempts += 1;
+            }
+        }
+        Ok(answers.into_iter().map(|answer| answer.unwrap()).collect())
+    }
+
+    /// Read the object, the first answer has its beginning, the rest is read in parallel. When
+    /// the object changes meanwhile, it is read again.
+    fn read(&mut self, name: &str, known: Option<&str>) -> io::Result<Remote> {
+        let encoded = labels(&base32(name.as_bytes()));
+        for _ in 0..ATTEMPTS {
+            let (first, _) = self.read_name(&encoded, known.unwrap_or("none"), "g")?;
+            let answer = self.query_all(&[first])?.remove(0);
+            let head = answer.first().map(|head| String::from_utf8_lossy(head).into_owned()).unwrap_or_default();
+            let (version, size) = match head.as_str() {
+                // a missing object still has the version `none`
+                "same" if known.is_none() => return Ok(Remote::Missing),
+                "same" => return Ok(Remote::Same),
+                "none" => return Ok(Remote::Missing),
+                head => {
+                    let mut parts = head.splitn(2, ' ');
+                    match (parts.next(), parts.next().and_then(|size| size.parse::<usize>().ok())) {
+                        (Some(version), Some(size)) => (version.to_owned(), size),
+                        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid dns answer '{}'", head))),
+                    }
+                }
+            };
+            let mut data = answer_data(&answer)?;
+            let mut queries = Vec::new();
+            let mut offset = data.len();
+            while offset < size {
+                let (query, len) = self.read_name(&encoded, &format!("{}.{}", version, offset), "r")?;
+                queries.push(query);
+                offset += len;
+            }
+            let mut gone = false;
+            for answer in self.query_all(&queries)? {
+                if answer.first().map(|status| status == b"gone").unwrap_or(false) {
+                    gone = true;
+                    break;
+                }
+                data.extend(answer_data(&answer)?);
+            }
+            if gone {
+                continue;
+            }
+            if data.len() != size {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("read {} bytes of {} from {} bytes", data.len(), name, size)));
+            }
+            return Ok(Remote::Object(version, data));
+        }
+        Err(io::Error::new(io::ErrorKind::Other, format!("{} is changing too fast to read it", name)))
+    }
+}
+
+impl Storage for DnsStorage {
+    /// The chunks of the data are uploaded in parallel, the last query puts them together.
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let (chunks, commit) = self.upload_names(name, data, &format!("{:08x}", unique()))?;
+        for answer in self.query_all(&chunks)? {
+            expect_ok(&answer)?;
+        }
+        expect_ok(&self.query_all(&[commit])?.remove(0))
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.read(name, None)? {
+            Remote::Object(_, data) => Ok(Some(data)),
+            _ => Ok(None),
+        }
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        let query = self.name(&format!("{}.d", labels(&base32(name.as_bytes()))))?;
+        expect_ok(&self.query_all(&[query])?.remove(0))
+    }
+
+    /// Every answer has the count of all the objects and as many of them as fits into it.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let encoded = if prefix.is_empty() { "-".to_owned() } else { labels(&base32(prefix.as_bytes())) };
+        let mut objects = Vec::new();
+        loop {
+            let query = self.name(&format!("{}.{}.l", encoded, objects.len()))?;
+            let answer = self.query_all(&[query])?.remove(0);
+            let total = answer.first()
+                .and_then(|total| String::from_utf8_lossy(total).parse::<usize>().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid dns list answer"))?;
+            let found = objects.len();
+            for entry in &answer[1..] {
+                let entry = String::from_utf8_lossy(entry);
+                let mut parts = entry.splitn(2, ' ');
+                if let (Some(modified), Some(name)) = (parts.next(), parts.next()) {
+                    objects.push(Object { name: name.to_owned(), modified: modified.parse().ok() });
+                }
+            }
+            // the objects can be removed meanwhile
+            if objects.len() >= total || objects.len() == found {
+                return Ok(objects);
+            }
+        }
+    }
+
+    /// The responder gives a new version to every write, the object is read only when its
+    /// version changes. The responder is asked at most once per the poll interval.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        let poll = Duration::from_millis(self.cfg.poll);
+        match self.checked.iter_mut().find(|&&mut (ref checked, _)| checked == name) {
+            Some(&mut (_, ref last)) if last.elapsed() < poll => return Ok(Fetch::Unchanged),
+            Some(&mut (_, ref mut last)) => *last = Instant::now(),
+            None => self.checked.push((name.to_owned(), Instant::now())),
+        }
+        match self.read(name, version.as_deref())? {
+            Remote::Same => Ok(Fetch::Unchanged),
+            Remote::Object(current, data) => {
+                *version = Some(current);
+                Ok(Fetch::Changed(data))
+            }
+            Remote::Missing => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+        }
+    }
+}
+
+/// How many bytes of the written data fit into the name of one upload query in the domain.
+fn chunk_len(domain: &str) -> usize {
+    let room = MAX_NAME.saturating_sub(domain.len() + UPLOAD_FIELDS);
+    (room - room / (MAX_LABEL + 1)) * 5 / 8
+}
+
+pub fn create_storage(mut cfg: DnsConfig) -> io::Result<Box<dyn Storage>> {
+    cfg.domain = cfg.domain.trim_matches('.').to_lowercase();
+    let chunk = chunk_len(&cfg.domain);
+    if cfg.domain.is_empty() || chunk < 16 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid dns domain '{}'", cfg.domain)));
+    }
+    let server = cfg.server.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid dns server '{}'", cfg.server)))?;
+    let socket = UdpSocket::bind(if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })?;
+    socket.connect(server)?;
+    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+    info!("Dns tunnel through {} in {}, {} bytes per write query", server, cfg.domain, chunk);
+    Ok(Box::new(DnsStorage {
+        cfg,
+        socket,
+        next_id: unique() as u16,
+        chunk,
+        checked: Vec::new(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::responder::{Responder, from_base32, parse_query, response};
+    use std::thread;
+
+    const DOMAIN: &str = "t.example.com";
+    const SECRET: &str = "tunnel secret";
+
+    fn storage(domain: &str) -> DnsStorage {
+        DnsStorage {
+            cfg: DnsConfig { server: "127.0.0.1:53".to_owned(), domain: domain.to_owned(), secret: SECRET.to_owned(), poll: 0 },
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            next_id: 0,
+            chunk: chunk_len(domain),
+            checked: Vec::new(),
+        }
+    }
+
+    /// Ask the responder directly, the answer has to fit into one udp message.
+    fn exchange(responder: &mut Responder, name: &str) -> Vec<Vec<u8>> {
+        let packet = query(7, name);
+        let parsed = parse_query(&packet).unwrap();
+        let (rcode, strings) = responder.handle(&parsed);
+        let answer = response(&parsed, rcode, strings);
+        assert!(answer.len() <= MAX_MESSAGE, "{} bytes of the answer to {}", answer.len(), name);
+        assert!(is_answer(&answer, &packet));
+        parse_answer(&answer, &packet).unwrap()
+    }
+
+    fn status(answer: &[Vec<u8>]) -> String {
+        String::from_utf8_lossy(&answer[0]).into_owned()
+    }
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 131 + i / 256) as u8).collect()
+    }
+
+    /// Read the whole object from the start with the version, the data from the first answer
+    /// and then the rest from the offsets.
+    fn read_all(storage: &DnsStorage, responder: &mut Responder, name: &str) -> Option<(String, Vec<u8>)> {
+        let encoded = labels(&base32(name.as_bytes()));
+        let (query, _) = storage.read_name(&encoded, "none", "g").unwrap();
+        let answer = exchange(responder, &query);
+        let head = status(&answer);
+        // the missing object still has the version `none`
+        if head == "same" {
+            return None;
+        }
+        let mut parts = head.split(' ');
+        let (version, size) = (parts.next().unwrap().to_owned(), parts.next().unwrap().parse::<usize>().unwrap());
+        let mut object = answer_data(&answer).unwrap();
+        while object.len() < size {
+            let (query, _) = storage.read_name(&encoded, &format!("{}.{}", version, object.len()), "r").unwrap();
+            let answer = exchange(responder, &query);
+            assert_eq!(status(&answer), head);
+            object.extend(answer_data(&answer).unwrap());
+        }
+        Some((version, object))
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        for len in 0..70 {
+            let data = data(len);
+            let encoded = base32(&data);
+            assert!(encoded.bytes().all(|c| BASE32.contains(&c)));
+            assert_eq!(encoded.len(), (len * 8).div_ceil(5));
+            assert_eq!(from_base32(&encoded), Some(data));
+        }
+        let all = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(from_base32(&base32(&all)), Some(all));
+        assert_eq!(from_base32("not base32!"), None);
+    }
+
+    #[test]
+    fn read_len_fits_answer() {
+        for domain in &[DOMAIN, "a-rather-long-subdomain.of-the-tunnel.example.com"] {
+            let storage = storage(domain);
+            let mut responder = Responder::new(domain, SECRET);
+            let object = data(5000);
+            for (transfer, name) in ["s/tunnel.in".to_owned(), format!("{}/tunnel.in", "long-session-name-".repeat(4))].iter().enumerate() {
+                let (chunks, commit) = storage.upload_names(name, &object, &transfer.to_string()).unwrap();
+                for chunk in &chunks {
+                    assert!(chunk.len() <= MAX_NAME && chunk.split('.').all(|label| label.len() <= MAX_LABEL));
+                    assert_eq!(status(&exchange(&mut responder, chunk)), "ok");
+                }
+                assert_eq!(status(&exchange(&mut responder, &commit)), "ok");
+                let encoded = labels(&base32(name.as_bytes()));
+                // the version and the offset are as long as they can be in the read queries
+                let (query, len) = storage.read_name(&encoded, "none", "g").unwrap();
+                assert_eq!(answer_data(&exchange(&mut responder, &query)).unwrap().len(), len);
+                let version = status(&exchange(&mut responder, &query)).split(' ').next().unwrap().to_owned();
+                let (query, len) = storage.read_name(&encoded, &format!("{}.1000", version), "r").unwrap();
+                assert_eq!(answer_data(&exchange(&mut responder, &query)).unwrap(), &object[1000..1000 + len]);
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_out_of_order() {
+        let storage = storage(DOMAIN);
+        let mut responder = Responder::new(DOMAIN, SECRET);
+        let object = data(1500);
+        let (chunks, commit) = storage.upload_names("s1/tunnel.in", &object, "7").unwrap();
+        assert!(chunks.len() > 3);
+        for chunk in chunks.iter().rev() {
+            assert_eq!(status(&exchange(&mut responder, chunk)), "ok");
+        }
+        // a repeated chunk is fine too
+        assert_eq!(status(&exchange(&mut responder, &chunks[1])), "ok");
+        assert_eq!(status(&exchange(&mut responder, &commit)), "ok");
+        assert_eq!(read_all(&storage, &mut responder, "s1/tunnel.in"), Some(("1".to_owned(), object)));
+    }
+
+    #[test]
+    fn missing_chunk() {
+        let storage = storage(DOMAIN);
+        let mut responder = Responder::new(DOMAIN, SECRET);
+        let object = data(1500);
+        let (chunks, commit) = storage.upload_names("s1/tunnel.in", &object, "7").unwrap();
+        for chunk in chunks.iter().filter(|chunk| *chunk != &chunks[2]) {
+            exchange(&mut responder, chunk);
+        }
+        assert_eq!(status(&exchange(&mut responder, &commit)), "incomplete");
+        assert_eq!(read_all(&storage, &mut responder, "s1/tunnel.in"), None);
+        exchange(&mut responder, &chunks[2]);
+        assert_eq!(status(&exchange(&mut responder, &commit)), "ok");
+        // the commit is repeated when its answer is lost, it is not a new version
+        assert_eq!(status(&exchange(&mut responder, &commit)), "ok");
+        assert_eq!(read_all(&storage, &mut responder, "s1/tunnel.in"), Some(("1".to_owned(), object)));
+        let encoded = labels(&base32(b"s1/tunnel.in"));
+        let (query, _) = storage.read_name(&encoded, "1", "g").unwrap();
+        assert_eq!(status(&exchange(&mut responder, &query)), "same");
+    }
+
+    #[test]
+    fn deleted_and_replaced_object() {
+        let storage = storage(DOMAIN);
+        let mut responder = Responder::new(DOMAIN, SECRET);
+        let encoded = labels(&base32(b"s1/tunnel.in"));
+        let (rest, _) = storage.read_name(&encoded, "1.500", "r").unwrap();
+        for (transfer, len) in &[("1", 1000), ("2", 800)] {
+            let (chunks, commit) = storage.upload_names("s1/tunnel.in", &data(*len), transfer).unwrap();
+            for chunk in &chunks {
+                exchange(&mut responder, chunk);
+            }
+            assert_eq!(status(&exchange(&mut responder, &commit)), "ok");
+        }
+        // the rest of the first version can't be read, the reader starts again
+        assert_eq!(status(&exchange(&mut responder, &rest)), "gone");
+        assert_eq!(read_all(&storage, &mut responder, "s1/tunnel.in"), Some(("2".to_owned(), data(800))));
+        let delete = storage.name(&format!("{}.d", encoded)).unwrap();
+        assert_eq!(status(&exchange(&mut responder, &delete)), "ok");
+        assert_eq!(status(&exchange(&mut responder, &delete)), "ok");
+        assert_eq!(read_all(&storage, &mut responder, "s1/tunnel.in"), None);
+        let (rest, _) = storage.read_name(&encoded, "2.500", "r").unwrap();
+        assert_eq!(status(&exchange(&mut responder, &rest)), "gone");
+    }
+
+    #[test]
+    fn refused_without_secret() {
+        let storage = storage(DOMAIN);
+        let mut responder = Responder::new(DOMAIN, "other secret");
+        let rcode = |responder: &mut Responder, name: &str| {
+            let (rcode, _) = responder.handle(&parse_query(&query(7, name)).unwrap());
+            rcode
+        };
+        let (chunks, commit) = storage.upload_names("s1/tunnel.in", &data(10), "7").unwrap();
+        assert_eq!(rcode(&mut responder, &chunks[0]), 5);
+        assert_eq!(rcode(&mut responder, &commit), 5);
+        let mut responder = Responder::new(DOMAIN, SECRET);
+        let (chunks, commit) = storage.upload_names("s1/tunnel.in", &data(10), "7").unwrap();
+        assert_eq!(rcode(&mut responder, &chunks[0]), 0);
+        // the mac doesn't fit the changed fields, or it is missing
+        let changed = commit.replacen(".10.", ".11.", 1);
+        assert_ne!(changed, commit);
+        assert_eq!(rcode(&mut responder, &changed), 5);
+        let labels = commit.split('.').collect::<Vec<_>>();
+        let mac = labels.len() - DOMAIN.split('.').count() - 1;
+        let unsigned = labels.iter().enumerate().filter(|&(i, _)| i != mac).map(|(_, label)| *label).collect::<Vec<_>>();
+        assert_eq!(rcode(&mut responder, &unsigned.join(".")), 5);
+        assert_eq!(rcode(&mut responder, &commit), 0);
+        assert_eq!(read_all(&storage, &mut responder, "s1/tunnel.in"), Some(("1".to_owned(), data(10))));
+    }
+
+    #[test]
+    fn round_trip_through_udp() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let server = socket.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let mut responder = Responder::new(DOMAIN, SECRET);
+            let mut buffer = [0; 4096];
+            for received in 1.. {
+                let (size, from) = socket.recv_from(&mut buffer).unwrap();
+                // one lost query, it is sent again
+                if received == 3 {
+                    continue;
+                }
+                let query = parse_query(&buffer[..size]).unwrap();
+                let (rcode, strings) = responder.handle(&query);
+                socket.send_to(&response(&query, rcode, strings), from).unwrap();
+            }
+        });
+        let mut storage = create_storage(DnsConfig { server, domain: DOMAIN.to_owned(), secret: SECRET.to_owned(), poll: 0 }).unwrap();
+        match storage.get_changed("s1/tunnel.in", &mut None).unwrap() {
+            Fetch::Missing => (),
+            _ => panic!("the object which is not there yet is not missing"),
+        }
+        let object = data(3000);
+        storage.put("s1/tunnel.in", &object).unwrap();
+        storage.put("s1/tunnel.lock", b"lock").unwrap();
+        assert_eq!(storage.get("s1/tunnel.in").unwrap(), Some(object.clone()));
+        let mut version = None;
+        match storage.get_changed("s1/tunnel.in", &mut version).unwrap() {
+            Fetch::Changed(data) => assert_eq!(data, object),
+            _ => panic!("the object is not read"),
+        }
+        match storage.get_changed("s1/tunnel.in", &mut version).unwrap() {
+            Fetch::Unchanged => (),
+            _ => panic!("the same object is read again"),
+        }
+        let mut names = storage.list("s1/").unwrap().into_iter().map(|object| object.name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["s1/tunnel.in", "s1/tunnel.lock"]);
+        storage.delete("s1/tunnel.in").unwrap();
+        assert_eq!(storage.get("s1/tunnel.in").unwrap(), None);
+        match storage.get_changed("s1/tunnel.in", &mut version).unwrap() {
+            Fetch::Missing => assert_eq!(version, None),
+            _ => panic!("the removed object is not missing"),
+        }
+    }
+}
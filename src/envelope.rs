@@ -0,0 +1,116 @@
+/**
+ * File: src/envelope.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+// Header of the tunnel objects, so the reader can recognize a truncated object or an older
+// version of the object, which some storages can return for a while after it is replaced.
+// The header is a yaml comment, so the object is still readable as it is:
+// `# tunnel <generation> <length> <checksum>`
+
+use std::str;
+
+const HEADER: &str = "# tunnel";
+
+/// Generation of the object, it grows with every write, also over restarts of the writer.
+pub struct Generation(u64);
+
+impl Generation {
+    pub fn new() -> Generation {
+        // at most one write every few milliseconds, so the milliseconds of the start are always
+        // bigger than the generations of the previous run
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        Generation(now.as_secs() * 1000 + now.subsec_nanos() as u64 / 1_000_000)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 += 1;
+        self.0
+    }
+}
+
+/// FNV-1a hash of the body.
+pub fn checksum(body: &[u8]) -> u64 {
+    body.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Add the header to the body of the object.
+pub fn seal(generation: &mut Generation, body: &[u8]) -> Vec<u8> {
+    let mut object = format!("{} {} {} {:016x}\n", HEADER, generation.next(), body.len(), checksum(body)).into_bytes();
+    object.extend_from_slice(body);
+    object
+}
+
+/// Check the header and return the generation and the body. Objects without the header, from
+/// older versions, have no generation.
+pub fn open(object: &[u8]) -> Result<(Option<u64>, &[u8]), String> {
+    if !object.starts_with(HEADER.as_bytes()) {
+        return Ok((None, object));
+    }
+    let end = object.iter().position(|b| *b == b'\n').ok_or("truncated header")?;
+    let header = str::from_utf8(&object[..end]).map_err(|e| e.to_string())?;
+    let body = &object[end + 1..];
+    let fields = header[HEADER.len()..].split_whitespace().collect::<Vec<_>>();
+    if fields.len() != 3 {
+        return Err(format!("invalid header '{}'", header));
+    }
+    let generation = fields[0].parse::<u64>().map_err(|e| e.to_string())?;
+    let len = fields[1].parse::<usize>().map_err(|e| e.to_string())?;
+    let sum = u64::from_str_radix(fields[2], 16).map_err(|e| e.to_string())?;
+    if body.len() != len {
+        return Err(format!("truncated object, {} of {} bytes", body.len(), len));
+    }
+    if checksum(body) != sum {
+        return Err("checksum mismatch".to_owned());
+    }
+    Ok((Some(generation), body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let mut generation = Generation(41);
+        let object = seal(&mut generation, b"- id: 1\n");
+        assert!(object.starts_with(b"# tunnel 42 8 "));
+        assert_eq!(open(&object), Ok((Some(42), &b"- id: 1\n"[..])));
+        let object = seal(&mut generation, b"");
+        assert_eq!(open(&object), Ok((Some(43), &b""[..])));
+    }
+
+    #[test]
+    fn truncated_body() {
+        let object = seal(&mut Generation(1), b"some messages");
+        let e = open(&object[..object.len() - 1]).unwrap_err();
+        assert!(e.starts_with("truncated object"), "{}", e);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut object = seal(&mut Generation(1), b"some messages");
+        let last = object.len() - 1;
+        object[last] ^= 1;
+        assert_eq!(open(&object), Err("checksum mismatch".to_owned()));
+    }
+
+    #[test]
+    fn legacy_object_without_header() {
+        let object = b"- id: 1\n  payload:\n    Keepalive: 7\n";
+        assert_eq!(open(object), Ok((None, &object[..])));
+    }
+
+    #[test]
+    fn malformed_header() {
+        assert_eq!(open(b"# tunnel 1 3 abc"), Err("truncated header".to_owned()));
+        assert!(open(b"# tunnel 1 3\nabc").unwrap_err().starts_with("invalid header"));
+        assert!(open(b"# tunnel x 3 0\nabc").is_err());
+        assert!(open(b"# tunnel 1 3 xyz\nabc").is_err());
+        assert!(open(b"# tunnel 1 3 0 0\nabc").unwrap_err().starts_with("invalid header"));
+    }
+}
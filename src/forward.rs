@@ -0,0 +1,272 @@
+/**
+ * File: src/forward.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::io::{self};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+
+use config::{ForwardMode, ForwardRule};
+use connection::{ClientState, Socket, Stream, run_connection};
+use messages::Endpoint;
+use tunnel::{WriterData, PeerStatus};
+use socks;
+use udp::{self, Flows};
+
+/// Connection ids of the listeners on the client side start from here, so they never collide with
+/// the ids created by the server side.
+pub const REVERSE_ID_BASE: u64 = 1 << 63;
+
+/// Seconds to wait for the proxy request on a new socks connection.
+const HANDSHAKE_TIMEOUT: u64 = 10;
+
+/// Tcp or unix listener.
+enum SocketListener {
+    Tcp(TcpListener),
+    Unix(UnixListener, String),
+}
+
+impl SocketListener {
+    fn bind(endpoint: &Endpoint) -> io::Result<SocketListener> {
+        match *endpoint {
+            Endpoint::Tcp(ref host, port) => {
+                let listener = TcpListener::bind((host.as_str(), port))?;
+                listener.set_nonblocking(true)?;
+                Ok(SocketListener::Tcp(listener))
+            }
+            Endpoint::Unix(ref path) => {
+                use std::fs;
+                use std::os::unix::fs::FileTypeExt;
+                // remove the socket left by previous run, but never any other file
+                if let Ok(metadata) = fs::symlink_metadata(path) {
+                    if metadata.file_type().is_socket() {
+                        fs::remove_file(path)?;
+                    }
+                }
+                let listener = UnixListener::bind(path)?;
+                listener.set_nonblocking(true)?;
+                Ok(SocketListener::Unix(listener, path.clone()))
+            }
+        }
+    }
+
+    /// Accept a new connection, returns the socket in the blocking mode and the peer address.
+    fn accept(&self) -> io::Result<(Socket, String)> {
+        match *self {
+            SocketListener::Tcp(ref listener) => {
+                let (socket, address) = listener.accept()?;
+                socket.set_nonblocking(false)?;
+                Ok((Socket::Tcp(socket), address.to_string()))
+            }
+            SocketListener::Unix(ref listener, ref path) => {
+                let (socket, _) = listener.accept()?;
+                socket.set_nonblocking(false)?;
+                Ok((Socket::Unix(socket), format!("unix:{}", path)))
+            }
+        }
+    }
+}
+
+impl Drop for SocketListener {
+    fn drop(&mut self) {
+        if let SocketListener::Unix(_, ref path) = *self {
+            let _ = ::std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Running listener of one forward rule.
+struct Listener {
+    /// The rule of the listener, the destination can be changed on reload while it is running.
+    rule: Arc<Mutex<ForwardRule>>,
+    /// When set to false, the listener stops accepting new connections.
+    running: Arc<AtomicBool>,
+    thread: thread::JoinHandle<()>,
+}
+
+/// All the listeners created from the forward rules.
+pub struct Forwards {
+    listeners: HashMap<String, Listener>,
+    /// Last used connection id, shared by all the listeners.
+    last_id: Arc<AtomicUsize>,
+    /// All connection ids are created from this base.
+    id_base: u64,
+    tunnel_writer: Sender<WriterData>,
+    client_state_sender: Sender<ClientState>,
+    /// Udp flows of the udp rules.
+    flows: Flows,
+    /// New connections are refused while the other side is not alive.
+    peer: PeerStatus,
+}
+
+impl Forwards {
+    pub fn new(id_base: u64, tunnel_writer: Sender<WriterData>, client_state_sender: Sender<ClientState>, flows: Flows, peer: PeerStatus) -> Forwards {
+        Forwards {
+            listeners: HashMap::new(),
+            last_id: Arc::new(AtomicUsize::new(0)),
+            id_base,
+            tunnel_writer,
+            client_state_sender,
+            flows,
+            peer,
+        }
+    }
+
+    /// Current forward rules.
+    pub fn rules(&self) -> Vec<ForwardRule> {
+        let mut rules = self.listeners
+            .values()
+            .map(|listener| listener.rule.lock().unwrap().clone())
+            .collect::<Vec<_>>();
+        rules.sort_by(|a, b| a.name.cmp(&b.name));
+        rules
+    }
+
+    /// Apply new set of rules. Listeners of removed rules are stopped, new rules get a new
+    /// listener and changed rules are updated in place, unless the bind or the mode changes, then
+    /// they get a new listener too. Connections which are already open are not touched.
+    pub fn update(&mut self, rules: Vec<ForwardRule>) {
+        let names = rules.iter().map(|rule| rule.name.clone()).collect::<Vec<_>>();
+        let removed = self.listeners
+            .keys()
+            .filter(|name| !names.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for name in removed {
+            info!("Removing forward rule '{}'", name);
+            self.stop(&name);
+        }
+        for rule in rules {
+            let rebind = match self.listeners.get(&rule.name) {
+                Some(listener) => {
+                    let mut current = listener.rule.lock().unwrap();
+                    // another mode can need another kind of socket, e.g. udp
+                    if current.bind == rule.bind && current.mode == rule.mode {
+                        if *current != rule {
+                            info!("Forward rule '{}' now connects to {}", rule.name, rule.destination());
+                            *current = rule.clone();
+                        }
+                        false
+                    } else {
+                        true
+                    }
+                }
+                None => true,
+            };
+            if rebind {
+                self.stop(&rule.name);
+                match self.start(rule.clone()) {
+                    Ok(listener) => {
+                        self.listeners.insert(rule.name.clone(), listener);
+                    }
+                    Err(e) => error!("Failed to listen on {} for the forward rule '{}': {}", rule.bind, rule.name, e),
+                }
+            }
+        }
+    }
+
+    fn stop(&mut self, name: &str) {
+        if let Some(listener) = self.listeners.remove(name) {
+            listener.running.store(false, Ordering::SeqCst);
+            // wait until the socket is closed, so the address can be used again
+            let _ = listener.thread.join();
+        }
+    }
+
+    fn start(&self, rule: ForwardRule) -> io::Result<Listener> {
+        let is_udp = rule.mode == ForwardMode::Udp;
+        let bind = io_res!(Endpoint::parse(&rule.bind), InvalidInput)?;
+        info!("Forward rule '{}': listening on {}, connecting to {}", rule.name, rule.bind, rule.destination());
+
+        let rule = Arc::new(Mutex::new(rule));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = if is_udp {
+            use std::net::UdpSocket;
+            let socket = match bind {
+                Endpoint::Tcp(ref host, port) => UdpSocket::bind((host.as_str(), port))?,
+                Endpoint::Unix(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "udp rule cannot listen on unix socket")),
+            };
+            udp::listen(socket, rule.clone(), running.clone(), self.id_base, self.last_id.clone(), self.tunnel_writer.clone(), self.flows.clone())?
+        } else {
+            self.listen(&bind, rule.clone(), running.clone())?
+        };
+
+        Ok(Listener {
+            rule,
+            running,
+            thread,
+        })
+    }
+
+    fn listen(&self, bind: &Endpoint, rule: Arc<Mutex<ForwardRule>>, running: Arc<AtomicBool>) -> io::Result<thread::JoinHandle<()>> {
+        let listener = SocketListener::bind(bind)?;
+
+        let last_id = self.last_id.clone();
+        let id_base = self.id_base;
+        let tunnel_writer = self.tunnel_writer.clone();
+        let client_state_sender = self.client_state_sender.clone();
+        let peer = self.peer.clone();
+        let thread = thread::spawn(move || {
+            while running.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((_, address)) if !peer.load(Ordering::SeqCst) => {
+                        warn!("Refusing connection from {} on '{}', the other side is not alive", address, rule.lock().unwrap().name);
+                    }
+                    Ok((mut socket, address)) => {
+                        let id = id_base + (last_id.fetch_add(1, Ordering::SeqCst) + 1) as u64;
+                        let rule = rule.lock().unwrap().clone();
+                        info!("[{}] Got connection from {} on '{}'", id, address, rule.name);
+                        let tunnel_writer = tunnel_writer.clone();
+                        let client_state_sender = client_state_sender.clone();
+                        match rule.mode {
+                            ForwardMode::Direct => {
+                                tunnel_writer.send(WriterData::Connect(id, rule.endpoint())).unwrap();
+                                run_connection(tunnel_writer, id, client_state_sender, socket);
+                            }
+                            ForwardMode::Udp => {
+                                // the socket is closed here, the listener keeps running
+                                error!("[{}] Refusing connection from {}, the rule '{}' is udp now", id, address, rule.name);
+                            }
+                            ForwardMode::Socks => {
+                                // the proxy handshake can take time, do not block the listener
+                                thread::spawn(move || {
+                                    use std::time::Duration;
+                                    let destination = socket
+                                        .set_read_timeout(Some(Duration::from_secs(HANDSHAKE_TIMEOUT)))
+                                        .and_then(|_| socks::handshake(&mut socket));
+                                    match destination {
+                                        Ok((host, port)) => {
+                                            info!("[{}] Proxy request to {}:{}", id, host, port);
+                                            tunnel_writer.send(WriterData::Connect(id, Endpoint::Tcp(host, port))).unwrap();
+                                            run_connection(tunnel_writer, id, client_state_sender, socket);
+                                        }
+                                        Err(e) => error!("[{}] Proxy handshake failed: {}", id, e),
+                                    }
+                                });
+                            }
+                        }
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        wait_little!();
+                    }
+                    Err(e) => {
+                        error!("Failed to accept a connection: {}", e);
+                        wait_little!();
+                    }
+                }
+            }
+            info!("Forward rule '{}' stopped listening", rule.lock().unwrap().name);
+        });
+        Ok(thread)
+    }
+}
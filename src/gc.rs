@@ -0,0 +1,74 @@
+/**
+ * File: src/gc.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self};
+use std::thread;
+use std::time::Duration;
+
+use storage::{Storage, Object};
+use tools::now_secs;
+use shutdown;
+
+/// How often the background collector runs.
+const GC_INTERVAL: u64 = 600;
+
+/// Extensions of the objects created by the tunnel, nothing else in the bucket is touched.
+const EXTENSIONS: [&str; 4] = [".in", ".out", ".lock", ".peer"];
+
+/// Tunnel objects under the prefix, which have not been modified for `age` seconds. The running
+/// tunnels rewrite their objects with every keepalive, so only the objects of the dead ones are
+/// found. Objects without the modification time are never stale.
+fn stale(storage: &mut dyn Storage, age: u64) -> io::Result<Vec<Object>> {
+    let now = now_secs();
+    Ok(storage.list("")?
+        .into_iter()
+        .filter(|object| EXTENSIONS.iter().any(|ext| object.name.ends_with(ext)))
+        .filter(|object| object.modified.map(|modified| now.saturating_sub(modified) > age).unwrap_or(false))
+        .collect())
+}
+
+/// Remove the stale tunnel objects, with `dry_run` they are just printed.
+pub fn collect(storage: &mut dyn Storage, age: u64, dry_run: bool) -> io::Result<()> {
+    let objects = stale(storage, age)?;
+    if objects.is_empty() {
+        println!("No stale objects");
+    }
+    let now = now_secs();
+    for object in objects {
+        let age = now.saturating_sub(object.modified.unwrap_or(0));
+        if dry_run {
+            println!("{} (modified {}s ago)", object.name, age);
+        } else {
+            storage.delete(&object.name)?;
+            println!("{} (modified {}s ago) removed", object.name, age);
+        }
+    }
+    Ok(())
+}
+
+/// Remove the stale tunnel objects periodically, until the shutdown.
+pub fn run_background(mut storage: Box<dyn Storage>, age: u64) {
+    thread::spawn(move || {
+        loop {
+            match stale(&mut *storage, age) {
+                Ok(objects) => {
+                    for object in objects {
+                        match storage.delete(&object.name) {
+                            Ok(_) => info!("Stale object {} is removed", object.name),
+                            Err(e) => error!("Failed to remove stale object {}: {}", object.name, e),
+                        }
+                    }
+                }
+                Err(e) => error!("Failed to list the objects: {}", e),
+            }
+            if shutdown::sleep(Duration::from_secs(GC_INTERVAL)) {
+                break;
+            }
+        }
+    });
+}
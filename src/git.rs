@@ -0,0 +1,289 @@
+/**
+ * File: src/git.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::fs::File;
+use std::io::{self};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use config::GitConfig;
+use storage::{Storage, Object, Fetch};
+use tools::{hostname, wait_with_input};
+
+/// Name of the file with the content of the object in every commit.
+const DATA: &str = "data";
+
+/// Lock file in the local repository, locked as long as the process using it runs.
+const LOCK: &str = "tunnel.lock";
+
+/// How many operations until the unreachable objects of the local repository are removed.
+const PRUNE_EVERY: usize = 500;
+
+/// Storage in the branches of a git remote, every object is one branch with just one commit.
+/// The commit has no parent and it is force pushed, so the branch never has any history, the
+/// old content is just an unreachable commit, which the git gc of the remote removes.
+pub struct GitStorage {
+    cfg: GitConfig,
+    /// Local bare repository, only for creating and reading the commits.
+    repo: PathBuf,
+    /// Locked lock file of the local repository.
+    _lock: File,
+    /// When the watched objects have been checked last time.
+    checked: Vec<(String, Instant)>,
+    operations: usize,
+}
+
+/// Branches can't have a component ending with `.lock`, so every component has `_` at the end.
+fn branch_name(cfg: &GitConfig, name: &str) -> String {
+    let components = name.split('/').map(|component| format!("{}_", component)).collect::<Vec<_>>();
+    format!("{}/{}", cfg.branch, components.join("/"))
+}
+
+/// Object name of the branch, None if it is not a tunnel branch.
+fn object_name(cfg: &GitConfig, branch: &str) -> Option<String> {
+    let name = branch.strip_prefix(&format!("refs/heads/{}/", cfg.branch))?;
+    name.split('/')
+        .map(|component| component.strip_suffix('_'))
+        .collect::<Option<Vec<_>>>()
+        .map(|components| components.join("/"))
+}
+
+impl GitStorage {
+    /// Run git in the local repository. The missing remote branch is NotFound error.
+    fn git(&self, args: &[&str], input: Option<&[u8]>) -> io::Result<Vec<u8>> {
+        use std::process::{Command, Stdio};
+        let child = Command::new("git")
+            .arg("--git-dir")
+            .arg(&self.repo)
+            .args(args)
+            .env("GIT_AUTHOR_NAME", "tunnel")
+            .env("GIT_AUTHOR_EMAIL", format!("tunnel@{}", hostname()))
+            .env("GIT_COMMITTER_NAME", "tunnel")
+            .env("GIT_COMMITTER_EMAIL", format!("tunnel@{}", hostname()))
+            .env("GIT_TERMINAL_PROMPT", "0")
+            // the errors are recognized by their messages, which must not be translated
+            .env("LC_ALL", "C")
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let output = wait_with_input(child, input)?;
+        if output.status.success() {
+            return Ok(output.stdout);
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let kind = if stderr.contains("couldn't find remote ref") || stderr.contains("remote ref does not exist") {
+            io::ErrorKind::NotFound
+        } else {
+            io::ErrorKind::Other
+        };
+        Err(io::Error::new(kind, format!("git {} failed: {}", args.join(" "), stderr.trim())))
+    }
+
+    fn git_string(&self, args: &[&str], input: Option<&[u8]>) -> io::Result<String> {
+        self.git(args, input)
+            .map(|output| String::from_utf8_lossy(&output).trim().to_owned())
+    }
+
+    /// The commits of the local repository are needed only until they are pushed or read.
+    fn prune(&mut self) {
+        self.operations += 1;
+        if self.operations >= PRUNE_EVERY {
+            self.operations = 0;
+            if let Err(e) = self.git(&["prune", "--expire=now"], None) {
+                warn!("Failed to prune {}: {}", self.repo.display(), e);
+            }
+        }
+    }
+
+    /// Commit of the branch on the remote, None if there is no such branch.
+    fn remote_commit(&self, name: &str) -> io::Result<Option<String>> {
+        let branch = format!("refs/heads/{}", branch_name(&self.cfg, name));
+        let output = self.git_string(&["ls-remote", &self.cfg.remote, &branch], None)?;
+        Ok(output.lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                match (parts.next(), parts.next()) {
+                    (Some(commit), Some(found)) if found == branch => Some(commit.to_owned()),
+                    _ => None,
+                }
+            })
+            .next())
+    }
+
+    /// Fetch the branch, returns the commit and the content.
+    fn fetch(&mut self, name: &str) -> io::Result<Option<(String, Vec<u8>)>> {
+        self.prune();
+        let branch = format!("refs/heads/{}", branch_name(&self.cfg, name));
+        match self.git(&["fetch", "--quiet", "--no-tags", &self.cfg.remote, &branch], None) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let commit = self.git_string(&["rev-parse", "FETCH_HEAD"], None)?;
+        let data = self.git(&["cat-file", "blob", &format!("{}:{}", commit, DATA)], None)?;
+        Ok(Some((commit, data)))
+    }
+}
+
+impl Storage for GitStorage {
+    /// The content is one file in a new commit without any parent, which replaces the branch.
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.prune();
+        let blob = self.git_string(&["hash-object", "-w", "--stdin"], Some(data))?;
+        let tree = self.git_string(&["mktree"], Some(format!("100644 blob {}\t{}\n", blob, DATA).as_bytes()))?;
+        let commit = self.git_string(&["commit-tree", &tree, "-m", &format!("tunnel {}", name)], None)?;
+        let refspec = format!("+{}:refs/heads/{}", commit, branch_name(&self.cfg, name));
+        self.git(&["push", "--quiet", &self.cfg.remote, &refspec], None)
+            .map(|_| ())
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.fetch(name)
+            .map(|fetched| fetched.map(|(_, data)| data))
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        let branch = format!("refs/heads/{}", branch_name(&self.cfg, name));
+        match self.git(&["push", "--quiet", &self.cfg.remote, "--delete", &branch], None) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    /// All the tunnel branches are fetched, the modification time is the time of the commit.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let pattern = format!("refs/heads/{}/*", self.cfg.branch);
+        let output = self.git_string(&["ls-remote", &self.cfg.remote, &pattern], None)?;
+        let branches = output.lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let commit = parts.next()?;
+                let name = object_name(&self.cfg, parts.next()?)?;
+                if name.starts_with(prefix) { Some((commit.to_owned(), name)) } else { None }
+            })
+            .collect::<Vec<_>>();
+        if branches.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut args = vec!["fetch".to_owned(), "--quiet".to_owned(), "--no-tags".to_owned(), self.cfg.remote.clone()];
+        args.extend(branches.iter().map(|(_, name)| format!("refs/heads/{}", branch_name(&self.cfg, name))));
+        // the branch can be removed meanwhile, then the times are just unknown
+        if let Err(e) = self.git(&args.iter().map(|arg| arg.as_str()).collect::<Vec<_>>(), None) {
+            warn!("Failed to fetch the tunnel branches: {}", e);
+        }
+        Ok(branches.into_iter()
+            .map(|(commit, name)| Object {
+                modified: self.git_string(&["show", "-s", "--format=%ct", &commit], None).ok().and_then(|time| time.parse().ok()),
+                name,
+            })
+            .collect())
+    }
+
+    /// The commit of the branch is the version, the content is fetched only when it changes.
+    /// The remote is asked at most once per the poll interval.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        let poll = Duration::from_millis(self.cfg.poll);
+        match self.checked.iter_mut().find(|&&mut (ref checked, _)| checked == name) {
+            Some(&mut (_, ref last)) if last.elapsed() < poll => return Ok(Fetch::Unchanged),
+            Some(&mut (_, ref mut last)) => *last = Instant::now(),
+            None => self.checked.push((name.to_owned(), Instant::now())),
+        }
+        match self.remote_commit(name)? {
+            Some(ref commit) if version.as_ref() == Some(commit) => Ok(Fetch::Unchanged),
+            Some(_) => {
+                match self.fetch(name)? {
+                    Some((commit, data)) => {
+                        *version = Some(commit);
+                        Ok(Fetch::Changed(data))
+                    }
+                    None => {
+                        *version = None;
+                        Ok(Fetch::Missing)
+                    }
+                }
+            }
+            None => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+        }
+    }
+}
+
+impl Drop for GitStorage {
+    fn drop(&mut self) {
+        use std::fs;
+        let _ = fs::remove_dir_all(&self.repo);
+    }
+}
+
+/// Lock the lock file of a local repository, None if it is locked by another process. The lock
+/// is released, when the file is closed, also when the process is killed.
+fn lock(file: File) -> io::Result<Option<File>> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        Ok(Some(file))
+    } else {
+        let e = io::Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EWOULDBLOCK) => Ok(None),
+            _ => Err(e),
+        }
+    }
+}
+
+/// Remove the local repositories of the processes, which are not running anymore, their lock
+/// files are not locked.
+fn remove_abandoned() {
+    use std::env;
+    use std::fs;
+    let entries = match fs::read_dir(env::temp_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        if !entry.file_name().to_string_lossy().starts_with("tunnel-git-") {
+            continue;
+        }
+        // the repository without the lock file is just being created or removed
+        let file = match File::open(entry.path().join(LOCK)) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+        if let Ok(Some(_lock)) = lock(file) {
+            info!("Removing abandoned git repository {}", entry.path().display());
+            let _ = fs::remove_dir_all(entry.path());
+        }
+    }
+}
+
+pub fn create_storage(cfg: GitConfig) -> io::Result<Box<dyn Storage>> {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    remove_abandoned();
+    // every storage has its own repository, FETCH_HEAD can't be shared
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let repo = env::temp_dir().join(format!("tunnel-git-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::SeqCst)));
+    // the repository is locked before git creates anything in it
+    fs::create_dir(&repo)?;
+    let lock = lock(OpenOptions::new().create(true).truncate(false).write(true).open(repo.join(LOCK))?)?;
+    let lock = io_res!(opt => lock, "the new git repository is locked by another process")?;
+    let storage = GitStorage {
+        cfg,
+        repo,
+        _lock: lock,
+        checked: Vec::new(),
+        operations: 0,
+    };
+    storage.git(&["init", "--quiet", "--bare"], None)?;
+    info!("Git repository {} for {}", storage.repo.display(), storage.cfg.remote);
+    Ok(Box::new(storage))
+}
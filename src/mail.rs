@@ -0,0 +1,626 @@
+/**
+ * File: src/mail.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use base64;
+use openssl::ssl::{SslMethod, SslConnectorBuilder, HandshakeError};
+
+use config::MailConfig;
+use storage::{Storage, Object, Fetch};
+use tools::{hostname, now_secs, parse_time};
+
+/// Subject of the tunnel mails, the name is between `<` and `>`, so the imap search for it
+/// doesn't find the longer names.
+const SUBJECT: &str = "[tunnel] <";
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+trait Stream: Read + Write + Send {}
+impl<S: Read + Write + Send> Stream for S {}
+
+/// Connect to the server, with tls from the start if it is configured.
+fn connect(address: &str, tls: bool) -> io::Result<BufReader<Box<dyn Stream>>> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    let stream: Box<dyn Stream> = Box::new(stream);
+    if tls {
+        start_tls(address, stream).map(BufReader::new)
+    } else {
+        Ok(BufReader::new(stream))
+    }
+}
+
+/// Start tls on the connection, the certificate has to be valid for the host of the address.
+fn start_tls(address: &str, stream: Box<dyn Stream>) -> io::Result<Box<dyn Stream>> {
+    let host = address.rsplitn(2, ':').last().unwrap_or(address);
+    let connector = io_res!(SslConnectorBuilder::new(SslMethod::tls()))?.build();
+    match connector.connect(host, stream) {
+        Ok(stream) => Ok(Box::new(stream)),
+        Err(HandshakeError::SetupFailure(e)) => Err(io::Error::new(io::ErrorKind::Other, format!("tls: {}", e))),
+        Err(HandshakeError::Failure(stream)) | Err(HandshakeError::Interrupted(stream)) =>
+            Err(io::Error::new(io::ErrorKind::Other, format!("tls handshake with {} failed: {}", host, stream.error()))),
+    }
+}
+
+/// Without tls the password would go in clear, it is allowed only by `plain_login`.
+fn refuse_plain(cfg: &MailConfig, server: &str) -> io::Result<()> {
+    if cfg.plain_login {
+        warn!("Sending the password to {} without tls", server);
+        return Ok(());
+    }
+    Err(io::Error::new(io::ErrorKind::PermissionDenied,
+        format!("{} doesn't offer STARTTLS, not sending the password without tls (see plain_login)", server)))
+}
+
+fn read_line(reader: &mut BufReader<Box<dyn Stream>>) -> io::Result<String> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+    }
+    Ok(line.trim_end_matches("\r\n").to_owned())
+}
+
+/// Date of the mail header, e.g. `Mon, 19 Oct 2026 09:15:30 +0000`.
+fn mail_date(secs: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    let days = (secs / 86400) as i64;
+    // civil date from the days, the year starts in march, so the leap day is the last one
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}, {} {} {} {:02}:{:02}:{:02} +0000",
+            DAYS[(days % 7) as usize], day, MONTHS[month as usize - 1], year,
+            secs / 3600 % 24, secs / 60 % 60, secs % 60)
+}
+
+/// Time of the imap internal date, e.g. `19-Oct-2026 09:15:30 +0200`.
+fn parse_internal_date(date: &str) -> Option<u64> {
+    let parts = date.split_whitespace().collect::<Vec<_>>();
+    let day = parts.first()?.split('-').collect::<Vec<_>>();
+    if parts.len() < 3 || day.len() != 3 {
+        return None;
+    }
+    let month = MONTHS.iter().position(|m| *m == day[1])? + 1;
+    let time = parse_time(&format!("{}-{}-{}", day[2], month, day[0]), parts[1])?;
+    let zone = parts[2];
+    let offset = zone.get(1..3)?.parse::<u64>().ok()? * 3600 + zone.get(3..5)?.parse::<u64>().ok()? * 60;
+    Some(if zone.starts_with('-') { time + offset } else { time.saturating_sub(offset) })
+}
+
+/// Quote the string for imap.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// One untagged response of the imap server, the literals are separately.
+struct Response {
+    text: String,
+    literals: Vec<Vec<u8>>,
+}
+
+/// Connection to the imap server with the folder selected.
+struct Imap {
+    reader: BufReader<Box<dyn Stream>>,
+    tag: usize,
+}
+
+impl Imap {
+    fn open(cfg: &MailConfig) -> io::Result<Imap> {
+        Imap::login(connect(&cfg.imap, cfg.tls)?, cfg)
+    }
+
+    /// Log in and select the folder, without tls the connection is switched to tls first.
+    fn login(reader: BufReader<Box<dyn Stream>>, cfg: &MailConfig) -> io::Result<Imap> {
+        let mut imap = Imap {
+            reader,
+            tag: 0,
+        };
+        read_line(&mut imap.reader)?;
+        if !cfg.tls {
+            let starttls = imap.command("CAPABILITY")?
+                .iter()
+                .any(|response| response.text.starts_with("* CAPABILITY") && response.text.split_whitespace().any(|cap| cap == "STARTTLS"));
+            if starttls {
+                imap.command("STARTTLS")?;
+                imap.reader = BufReader::new(start_tls(&cfg.imap, imap.reader.into_inner())?);
+            } else {
+                refuse_plain(cfg, &cfg.imap)?;
+            }
+        }
+        imap.command(&format!("LOGIN {} {}", quote(&cfg.username), quote(&cfg.password)))?;
+        imap.command(&format!("SELECT {}", quote(&cfg.folder)))?;
+        Ok(imap)
+    }
+
+    /// Send the command, returns the untagged responses.
+    fn command(&mut self, command: &str) -> io::Result<Vec<Response>> {
+        self.tag += 1;
+        let tag = format!("t{}", self.tag);
+        self.reader.get_mut().write_all(format!("{} {}\r\n", tag, command).as_bytes())?;
+        let mut responses = Vec::new();
+        loop {
+            let mut line = read_line(&mut self.reader)?;
+            if line.starts_with(&format!("{} ", tag)) {
+                if line[tag.len() + 1..].starts_with("OK") {
+                    return Ok(responses);
+                }
+                return Err(io::Error::new(io::ErrorKind::Other, format!("imap: {}", line)));
+            }
+            let mut response = Response { text: String::new(), literals: Vec::new() };
+            // the literal `{size}` at the end of the line is followed by the data and the rest
+            // of the response
+            loop {
+                let size = if line.ends_with('}') {
+                    line.rfind('{').and_then(|start| line[start + 1..line.len() - 1].parse::<usize>().ok())
+                } else {
+                    None
+                };
+                response.text.push_str(&line);
+                match size {
+                    Some(size) => {
+                        let mut literal = vec![0; size];
+                        self.reader.read_exact(&mut literal)?;
+                        response.literals.push(literal);
+                        line = read_line(&mut self.reader)?;
+                    }
+                    None => break,
+                }
+            }
+            responses.push(response);
+        }
+    }
+
+    /// Uids of the tunnel mails with the subject.
+    fn search(&mut self, subject: &str) -> io::Result<Vec<u32>> {
+        // the new mails are seen after noop
+        self.command("NOOP")?;
+        let responses = self.command(&format!("UID SEARCH SUBJECT {}", quote(subject)))?;
+        let mut uids = responses.iter()
+            .filter_map(|response| response.text.strip_prefix("* SEARCH"))
+            .flat_map(|uids| uids.split_whitespace().filter_map(|uid| uid.parse().ok()).collect::<Vec<_>>())
+            .collect::<Vec<u32>>();
+        uids.sort_unstable();
+        Ok(uids)
+    }
+
+    fn delete(&mut self, uids: &[u32]) -> io::Result<()> {
+        if uids.is_empty() {
+            return Ok(());
+        }
+        let uids = uids.iter().map(|uid| uid.to_string()).collect::<Vec<_>>().join(",");
+        self.command(&format!("UID STORE {} +FLAGS.SILENT (\\Deleted)", uids))?;
+        self.command("EXPUNGE").map(|_| ())
+    }
+}
+
+/// Smtp session, which is kept open for all the mails, so the server is not asked to log in
+/// for every write.
+struct Smtp {
+    reader: BufReader<Box<dyn Stream>>,
+}
+
+impl Smtp {
+    fn open(cfg: &MailConfig) -> io::Result<Smtp> {
+        Smtp::login(connect(&cfg.smtp, cfg.tls)?, cfg)
+    }
+
+    /// Greet and log in, without tls the connection is switched to tls first.
+    fn login(reader: BufReader<Box<dyn Stream>>, cfg: &MailConfig) -> io::Result<Smtp> {
+        let mut smtp = Smtp { reader };
+        smtp.reply('2')?;
+        let capabilities = smtp.command(&format!("EHLO {}", hostname()), '2')?;
+        if !cfg.tls {
+            if capabilities.iter().any(|line| line.get(4..).map(|cap| cap.eq_ignore_ascii_case("STARTTLS")).unwrap_or(false)) {
+                smtp.command("STARTTLS", '2')?;
+                smtp.reader = BufReader::new(start_tls(&cfg.smtp, smtp.reader.into_inner())?);
+                // the session starts again with tls
+                smtp.command(&format!("EHLO {}", hostname()), '2')?;
+            } else {
+                refuse_plain(cfg, &cfg.smtp)?;
+            }
+        }
+        let login = base64::encode(format!("\0{}\0{}", cfg.username, cfg.password).as_bytes());
+        smtp.command(&format!("AUTH PLAIN {}", login), '2')?;
+        Ok(smtp)
+    }
+
+    /// Read the reply, which has to start with the expected digit. Returns its lines, the reply
+    /// can have more of them, the last one has a space after the code.
+    fn reply(&mut self, expected: char) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let line = read_line(&mut self.reader)?;
+            let last = line.len() < 4 || line.as_bytes()[3] != b'-';
+            lines.push(line);
+            if last {
+                break;
+            }
+        }
+        if lines.last().unwrap().starts_with(expected) {
+            Ok(lines)
+        } else {
+            Err(io::Error::new(io::ErrorKind::Other, format!("smtp: {}", lines.last().unwrap())))
+        }
+    }
+
+    fn command(&mut self, command: &str, expected: char) -> io::Result<Vec<String>> {
+        self.reader.get_mut().write_all(format!("{}\r\n", command).as_bytes())?;
+        self.reply(expected)
+    }
+
+    /// Send the data to the address itself, as the base64 body of the mail.
+    fn send(&mut self, cfg: &MailConfig, name: &str, data: &[u8]) -> io::Result<()> {
+        self.command(&format!("MAIL FROM:<{}>", cfg.address), '2')?;
+        self.command(&format!("RCPT TO:<{}>", cfg.address), '2')?;
+        self.command("DATA", '3')?;
+        // base64 lines never start with a dot, nothing to escape
+        let mail = format!("From: <{0}>\r\nTo: <{0}>\r\nSubject: {1}{2}>\r\nDate: {3}\r\nMIME-Version: 1.0\r\n\
+                            Content-Type: application/octet-stream\r\nContent-Transfer-Encoding: base64\r\n\r\n{4}\r\n.",
+                           cfg.address, SUBJECT, name, mail_date(now_secs()), base64::encode_config(data, base64::MIME));
+        self.command(&mail, '2').map(|_| ())
+    }
+}
+
+/// Storage in a mail account. Every write is a mail sent by smtp, with the name in the subject
+/// and the data as the base64 body of the mail. The reader finds the newest mail of the file by imap,
+/// the older ones have been replaced, so they are deleted. The server is asked at most once
+/// per the poll interval.
+pub struct MailStorage {
+    cfg: MailConfig,
+    /// Opened on the first use and after any failure.
+    imap: Option<Imap>,
+    /// Opened on the first write and after any failure.
+    smtp: Option<Smtp>,
+    /// When the watched files have been checked last time.
+    checked: Vec<(String, Instant)>,
+}
+
+impl MailStorage {
+    /// Run the imap operation on the connection, which is opened again after a failure.
+    fn imap<T, F: FnOnce(&mut Imap) -> io::Result<T>>(&mut self, operation: F) -> io::Result<T> {
+        if self.imap.is_none() {
+            self.imap = Some(Imap::open(&self.cfg)?);
+        }
+        let result = operation(self.imap.as_mut().unwrap());
+        if result.is_err() {
+            self.imap = None;
+        }
+        result
+    }
+
+    /// Send the mail through the open smtp session, a session which has been closed by the
+    /// server in the meantime is opened again.
+    fn send(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        if let Some(mut smtp) = self.smtp.take() {
+            match smtp.send(&self.cfg, name, data) {
+                Ok(()) => {
+                    self.smtp = Some(smtp);
+                    return Ok(());
+                }
+                Err(e) => info!("Opening the smtp session again: {}", e),
+            }
+        }
+        let mut smtp = Smtp::open(&self.cfg)?;
+        smtp.send(&self.cfg, name, data)?;
+        self.smtp = Some(smtp);
+        Ok(())
+    }
+
+    /// The newest mail of the file, returns its uid and the data. The older mails are deleted.
+    fn newest(&mut self, name: &str, known: Option<u32>) -> io::Result<Option<(u32, Option<Vec<u8>>)>> {
+        let subject = format!("{}{}>", SUBJECT, name);
+        self.imap(|imap| {
+            let uids = imap.search(&subject)?;
+            let newest = match uids.last() {
+                Some(newest) => *newest,
+                None => return Ok(None),
+            };
+            imap.delete(&uids[..uids.len() - 1])?;
+            if Some(newest) == known {
+                return Ok(Some((newest, None)));
+            }
+            let responses = imap.command(&format!("UID FETCH {} (BODY.PEEK[TEXT])", newest))?;
+            let body = responses.into_iter()
+                .flat_map(|response| response.literals)
+                .next()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("no body of the mail {}", newest)))?;
+            let data = io_res!(base64::decode_config(&body, base64::MIME), InvalidData)?;
+            Ok(Some((newest, Some(data))))
+        })
+    }
+}
+
+impl Storage for MailStorage {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.send(name, data)?;
+        // the mail can take a while to arrive, so at least the older ones are deleted
+        let subject = format!("{}{}>", SUBJECT, name);
+        self.imap(|imap| {
+            let uids = imap.search(&subject)?;
+            imap.delete(&uids[..uids.len().saturating_sub(1)])
+        })
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.newest(name, None)
+            .map(|newest| newest.and_then(|(_, data)| data))
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        let subject = format!("{}{}>", SUBJECT, name);
+        self.imap(|imap| {
+            let uids = imap.search(&subject)?;
+            imap.delete(&uids)
+        })
+    }
+
+    /// The newest mail of every file, the modification time is when it has arrived.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let subject = format!("{}{}", SUBJECT, prefix);
+        let responses = self.imap(|imap| {
+            let uids = imap.search(&subject)?;
+            if uids.is_empty() {
+                return Ok(Vec::new());
+            }
+            let uids = uids.iter().map(|uid| uid.to_string()).collect::<Vec<_>>().join(",");
+            imap.command(&format!("UID FETCH {} (UID INTERNALDATE BODY.PEEK[HEADER.FIELDS (SUBJECT)])", uids))
+        })?;
+        let mut newest = HashMap::new();
+        for response in responses {
+            let uid = response.text.split("UID ").nth(1)
+                .and_then(|rest| rest.split(|c: char| !c.is_ascii_digit()).next())
+                .and_then(|uid| uid.parse::<u32>().ok());
+            let modified = response.text.split("INTERNALDATE \"").nth(1)
+                .and_then(|rest| rest.split('"').next())
+                .and_then(parse_internal_date);
+            let header = response.literals.first().map(|header| String::from_utf8_lossy(header).into_owned()).unwrap_or_default();
+            let name = header.split(SUBJECT).nth(1)
+                .and_then(|rest| rest.split('>').next())
+                .map(|name| name.to_owned());
+            if let (Some(uid), Some(name)) = (uid, name) {
+                let entry = newest.entry(name).or_insert((uid, modified));
+                if uid >= entry.0 {
+                    *entry = (uid, modified);
+                }
+            }
+        }
+        Ok(newest.into_iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .map(|(name, (_, modified))| Object { name, modified })
+            .collect())
+    }
+
+    /// The uid of the newest mail is the version, the mail is read only when it changes.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        let poll = Duration::from_millis(self.cfg.poll);
+        match self.checked.iter_mut().find(|&&mut (ref checked, _)| checked == name) {
+            Some(&mut (_, ref last)) if last.elapsed() < poll => return Ok(Fetch::Unchanged),
+            Some(&mut (_, ref mut last)) => *last = Instant::now(),
+            None => self.checked.push((name.to_owned(), Instant::now())),
+        }
+        let known = version.as_ref().and_then(|version| version.parse().ok());
+        match self.newest(name, known)? {
+            Some((_, None)) => Ok(Fetch::Unchanged),
+            Some((uid, Some(data))) => {
+                *version = Some(uid.to_string());
+                Ok(Fetch::Changed(data))
+            }
+            None => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+        }
+    }
+}
+
+pub fn create_storage(cfg: MailConfig) -> io::Result<Box<dyn Storage>> {
+    Ok(Box::new(MailStorage {
+        cfg,
+        imap: None,
+        smtp: None,
+        checked: Vec::new(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Server with the replies prepared, what the client writes is kept.
+    struct Script {
+        replies: io::Cursor<Vec<u8>>,
+        sent: Sent,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What the client has written to the script.
+    type Sent = Arc<Mutex<Vec<u8>>>;
+
+    fn script(replies: &str) -> (BufReader<Box<dyn Stream>>, Sent) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let stream: Box<dyn Stream> = Box::new(Script {
+            replies: io::Cursor::new(replies.replace('\n', "\r\n").into_bytes()),
+            sent: sent.clone(),
+        });
+        (BufReader::new(stream), sent)
+    }
+
+    fn sent(sent: &Sent) -> String {
+        String::from_utf8_lossy(&sent.lock().unwrap()).into_owned()
+    }
+
+    fn config(plain_login: bool) -> MailConfig {
+        MailConfig {
+            // nothing listens there, so a new connection fails
+            smtp: "127.0.0.1:1".to_owned(),
+            imap: "127.0.0.1:1".to_owned(),
+            tls: false,
+            username: "user".to_owned(),
+            password: "secret".to_owned(),
+            plain_login,
+            address: "tunnel@example.com".to_owned(),
+            folder: "INBOX".to_owned(),
+            poll: 0,
+        }
+    }
+
+    fn storage(imap: Option<Imap>, smtp: Option<Smtp>) -> MailStorage {
+        MailStorage {
+            cfg: config(false),
+            imap,
+            smtp,
+            checked: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn smtp_reply_with_more_lines() {
+        let (reader, _) = script("250-mail.example.com\n250-SIZE 1000\n250 STARTTLS\n550 no such user\n");
+        let mut smtp = Smtp { reader };
+        assert_eq!(smtp.reply('2').unwrap(), vec!["250-mail.example.com", "250-SIZE 1000", "250 STARTTLS"]);
+        assert!(smtp.reply('2').is_err());
+    }
+
+    #[test]
+    fn smtp_refuses_login_without_tls() {
+        let (reader, written) = script("220 hello\n250-mail.example.com\n250 AUTH PLAIN\n");
+        let e = Smtp::login(reader, &config(false)).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!sent(&written).contains("AUTH"));
+    }
+
+    #[test]
+    fn smtp_plain_login_when_allowed() {
+        let (reader, written) = script("220 hello\n250-mail.example.com\n250 AUTH PLAIN\n235 ok\n");
+        Smtp::login(reader, &config(true)).unwrap();
+        assert!(sent(&written).contains(&format!("AUTH PLAIN {}\r\n", base64::encode(b"\0user\0secret"))));
+    }
+
+    #[test]
+    fn smtp_starttls_before_login() {
+        // the tls handshake fails on the script, the password must not be sent before it
+        let (reader, written) = script("220 hello\n250-mail.example.com\n250-STARTTLS\n250 AUTH PLAIN\n220 go ahead\n");
+        assert!(Smtp::login(reader, &config(true)).is_err());
+        let written = sent(&written);
+        assert!(written.contains("STARTTLS\r\n"));
+        assert!(!written.contains("AUTH"));
+    }
+
+    #[test]
+    fn smtp_session_is_kept() {
+        let (reader, written) = script("250 ok\n250 ok\n354 go\n250 queued\n250 ok\n250 ok\n354 go\n250 queued\n");
+        let mut storage = storage(None, Some(Smtp { reader }));
+        storage.send("s1/tunnel.in", b"first").unwrap();
+        storage.send("s1/tunnel.in", b"second").unwrap();
+        let written = sent(&written);
+        assert_eq!(written.matches("MAIL FROM:<tunnel@example.com>").count(), 2);
+        assert!(written.contains("Subject: [tunnel] <s1/tunnel.in>"));
+        assert!(written.contains(&base64::encode(b"second")));
+        // the session is still open
+        assert!(storage.smtp.is_some());
+    }
+
+    #[test]
+    fn imap_refuses_login_without_tls() {
+        let (reader, written) = script("* OK ready\n* CAPABILITY IMAP4rev1 AUTH=PLAIN\nt1 OK done\n");
+        let e = Imap::login(reader, &config(false)).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert!(!sent(&written).contains("LOGIN"));
+    }
+
+    #[test]
+    fn imap_search_sorts_uids() {
+        let (reader, written) = script("t1 OK noop\n* SEARCH 12 3 7\nt2 OK search\n");
+        let mut imap = Imap { reader, tag: 0 };
+        assert_eq!(imap.search("[tunnel] <a>").unwrap(), vec![3, 7, 12]);
+        assert!(sent(&written).contains("t2 UID SEARCH SUBJECT \"[tunnel] <a>\"\r\n"));
+    }
+
+    #[test]
+    fn imap_command_fails_on_no() {
+        let (reader, _) = script("* BYE\nt1 NO nope\n");
+        let mut imap = Imap { reader, tag: 0 };
+        assert!(imap.command("NOOP").is_err());
+    }
+
+    #[test]
+    fn newest_mail_is_read_and_older_deleted() {
+        let body = base64::encode(b"tunnel data");
+        let replies = format!("t1 OK\n* SEARCH 5 9 7\nt2 OK\nt3 OK\nt4 OK\n\
+                               * 2 FETCH (UID 9 BODY[TEXT] {{{}}}\n{})\nt5 OK\n", body.len(), body);
+        let (reader, written) = script(&replies);
+        let mut storage = storage(Some(Imap { reader, tag: 0 }), None);
+        let mut version = None;
+        match storage.get_changed("s1/tunnel.out", &mut version).unwrap() {
+            Fetch::Changed(data) => assert_eq!(data, b"tunnel data"),
+            _ => panic!("the newest mail is not read"),
+        }
+        assert_eq!(version, Some("9".to_owned()));
+        let written = sent(&written);
+        assert!(written.contains("UID STORE 5,7 +FLAGS.SILENT (\\Deleted)"));
+        assert!(written.contains("UID FETCH 9 (BODY.PEEK[TEXT])"));
+    }
+
+    #[test]
+    fn known_mail_is_not_read_again() {
+        let (reader, written) = script("t1 OK\n* SEARCH 9\nt2 OK\n");
+        let mut storage = storage(Some(Imap { reader, tag: 0 }), None);
+        let mut version = Some("9".to_owned());
+        match storage.get_changed("s1/tunnel.out", &mut version).unwrap() {
+            Fetch::Unchanged => (),
+            _ => panic!("the known mail is read again"),
+        }
+        assert!(!sent(&written).contains("FETCH"));
+    }
+
+    #[test]
+    fn no_mail_is_missing() {
+        let (reader, _) = script("t1 OK\n* SEARCH\nt2 OK\n");
+        let mut storage = storage(Some(Imap { reader, tag: 0 }), None);
+        let mut version = Some("9".to_owned());
+        match storage.get_changed("s1/tunnel.out", &mut version).unwrap() {
+            Fetch::Missing => assert_eq!(version, None),
+            _ => panic!("no mail is not missing"),
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(mail_date(0), "Thu, 1 Jan 1970 00:00:00 +0000");
+        assert_eq!(mail_date(951782400), "Tue, 29 Feb 2000 00:00:00 +0000");
+        assert_eq!(parse_internal_date("01-Jan-1970 02:00:00 +0200"), Some(0));
+        assert_eq!(parse_internal_date("29-Feb-2000 00:00:00 +0000"), Some(951782400));
+        assert_eq!(parse_internal_date("garbage"), None);
+    }
+}
@@ -2,7 +2,7 @@
  * File: src/main.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 04.09.2017
- * Last Modified Date: 11.10.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 extern crate aws_sdk_rust;
@@ -17,6 +17,11 @@ extern crate tokio_proto;
 extern crate tokio_service;
 
 extern crate rustyline;
+extern crate libc;
+extern crate hyper;
+extern crate hyper_openssl;
+extern crate openssl;
+extern crate base64;
 
 #[macro_use]
 extern crate clap;
@@ -27,6 +32,7 @@ extern crate log4rs;
 
 #[macro_use]
 extern crate serde_derive;
+extern crate serde;
 extern crate serde_yaml;
 
 use std::io::{self};
@@ -50,35 +56,122 @@ mod tunnel;
 mod server;
 mod client;
 mod connection;
+mod forward;
+mod socks;
+mod stdio;
+mod udp;
+mod storage;
+mod session;
+mod presence;
+mod shutdown;
+mod gc;
+mod envelope;
+mod command;
+mod relay;
+mod webdav;
+mod git;
+mod redis;
+mod sql;
+mod mail;
+mod dns;
+mod multi;
+mod split;
 
 use config::*;
 use clap::ArgMatches;
 
+/// Connect to the storage of the tunnel api, with `api:section` the config of the tunnel api is
+/// in the section instead of its usual place.
+fn create_api(tunnel_api: &str) -> io::Result<Box<dyn storage::Storage>> {
+    let (api, section) = match tunnel_api.find(':') {
+        Some(pos) => (&tunnel_api[..pos], Some(&tunnel_api[pos + 1..])),
+        None => (tunnel_api, None),
+    };
+    match api {
+        "aws"     => load_api(section, load_config).and_then(s3tunnel::create_storage),
+        "s3cmd"   => load_api(section, load_config).and_then(s3tunnel_cmd::create_storage),
+        "command" => load_api(section, load_command).and_then(command::create_storage),
+        "relay"   => load_api(section, load_relay).and_then(relay::create_storage),
+        "webdav"  => load_api(section, load_webdav).and_then(webdav::create_storage),
+        "git"     => load_api(section, load_git).and_then(git::create_storage),
+        "redis"   => load_api(section, load_redis).and_then(redis::create_storage),
+        "sql"     => load_api(section, load_sql).and_then(sql::create_storage),
+        "mail"    => load_api(section, load_mail).and_then(mail::create_storage),
+        "dns"     => load_api(section, load_dns).and_then(dns::create_storage),
+        "multi"   => load_api(section, load_multi).and_then(|cfg| multi::create_storage(cfg, create_api)),
+        _         => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown tunnel api '{}'", tunnel_api))),
+    }
+}
+
+/// Connect to the storage selected by the tunnel api.
+fn create_storage(matches: &ArgMatches) -> io::Result<Box<dyn storage::Storage>> {
+    let tunnel_api = matches.value_of("tunnel-api").unwrap();
+    if tunnel_api == "split" {
+        // only the client writes through the client tunnel api, the other modes just read
+        let is_client = matches.value_of("mode") == Some("client");
+        return load_split().and_then(|cfg| split::create_storage(cfg, is_client, create_api));
+    }
+    create_api(tunnel_api)
+}
+
+fn keepalive(matches: &ArgMatches) -> tunnel::Keepalive {
+    use std::time::Duration;
+    tunnel::Keepalive {
+        interval: Duration::from_secs(value_t!(matches, "keepalive", u64).unwrap()),
+        peer_timeout: Duration::from_secs(value_t!(matches, "peer-timeout", u64).unwrap()),
+    }
+}
+
 fn s3run(matches: &ArgMatches) -> io::Result<()> {
     let mode = &matches.value_of("mode").unwrap();
+    if mode == &"sessions" {
+        return create_storage(matches)
+            .and_then(|mut storage| session::list(&mut *storage));
+    }
+    if mode == &"peers" {
+        return create_storage(matches)
+            .and_then(|mut storage| presence::list(&mut *storage));
+    }
+    if mode == &"gc" {
+        let age = value_t!(matches, "gc-age", u64).unwrap();
+        return create_storage(matches)
+            .and_then(|mut storage| gc::collect(&mut *storage, age, matches.is_present("dry-run")));
+    }
+    shutdown::install();
+    if (mode == &"server" || mode == &"client") && !matches.is_present("no-gc") {
+        let age = value_t!(matches, "gc-age", u64).unwrap();
+        gc::run_background(create_storage(matches)?, age);
+    }
     let file_name = matches.value_of("tunnel-file-name").unwrap();
-    let is_server = mode == &"server";
+    if mode == &"client" && matches.is_present("all-sessions") {
+        let heartbeat = presence::heartbeat(mode, None);
+        return client::run_sessions(file_name, keepalive(matches), heartbeat, || create_storage(matches));
+    }
+    let session = session::Session::new(matches.value_of("session"), file_name);
+    // stdio mode is the server side of the tunnel with just one connection
+    let is_server = mode == &"server" || mode == &"stdio";
     let (writer_name , reader_name ) = if is_server {
-        (format!("{}.in",  file_name), format!("{}.out", file_name))
+        (session.server_to_client(), session.client_to_server())
     } else {
-        (format!("{}.out", file_name), format!("{}.in",  file_name))
+        (session.client_to_server(), session.server_to_client())
     };
-    load_config()
-        .and_then( |cfg| {
-            let tunnel_api = &matches.value_of("tunnel-api").unwrap();
-            match tunnel_api {
-                &"s3cmd" => s3tunnel_cmd::create_clients(is_server, cfg, &writer_name, &reader_name),
-                _        => s3tunnel::create_clients(is_server,     cfg, &writer_name, &reader_name),
+    create_storage(matches)
+        .and_then( |mut storage| {
+            let mut periodic = vec![presence::heartbeat(mode, matches.value_of("session"))];
+            if is_server {
+                session::acquire(&mut *storage, &session)?;
+                periodic.push(session::refresh_lock(&session));
             }
+            storage::create_pipes(is_server, storage, &writer_name, &reader_name, periodic)
         })
         .and_then( |tunnel_pipes| {
-            tunnel::run(is_server, tunnel_pipes)
+            tunnel::run(tunnel_pipes, keepalive(matches))
         })
         .and_then( |tunnel| {
-            if is_server {
-                server::run(matches, tunnel)
-            } else {
-                client::run(matches, tunnel)
+            match mode {
+                &"server" => server::run(matches, tunnel),
+                &"stdio"  => stdio::run(matches, tunnel),
+                _         => client::run(matches, tunnel),
             }
         })
 }
@@ -117,19 +210,49 @@ fn main() {
              .default_value("22")
              .validator(|val| val.parse::<u16>().map(|_| ()).map_err(|_| format!("Cannot parse {} to u16", val))))
         .arg(Arg::with_name("mode")
-             .help("Server or client mode.")
+             .help("Server or client mode, stdio is the server mode piping stdin and stdout through one connection, sessions lists the sessions in the bucket, peers lists the running endpoints, gc removes the stale tunnel files.")
              .index(1)
-             .possible_values(&["server", "client"])
+             .possible_values(&["server", "client", "stdio", "sessions", "peers", "gc"])
              .required(true))
         .arg(Arg::with_name("tunnel-api")
              .long("tunnel-api")
-             .help("Aws or s3cmd tunnel api communication.")
-             .possible_values(&["aws", "s3cmd"])
+             .help("Aws, s3cmd, shell commands from the config, tunnel-relay, webdav, git, redis, sql, mail, tunnel-dns, more of them from the multi section or one for each direction from the split section tunnel api communication.")
+             .possible_values(&["aws", "s3cmd", "command", "relay", "webdav", "git", "redis", "sql", "mail", "dns", "multi", "split"])
              .default_value("aws"))
         .arg(Arg::with_name("tunnel-file-name")
             .help("Name of the files to use for transfer data: tunnel.in tunnel.out, this is just the name, not the extension.")
             .long("tunnel-file-name")
             .default_value("tunnel"))
+        .arg(Arg::with_name("session")
+            .help("Name of the session, the tunnel files are in the directory of this name, so more tunnels can share one bucket.")
+            .long("session")
+            .takes_value(true)
+            .validator(|val| if val.is_empty() || val.contains('/') { Err(format!("Invalid session name '{}'", val)) } else { Ok(()) }))
+        .arg(Arg::with_name("keepalive")
+            .help("How often to let the other side know this side is alive, in seconds.")
+            .long("keepalive")
+            .default_value("10")
+            .validator(|val| val.parse::<u64>().map(|_| ()).map_err(|_| format!("Cannot parse {} to u64", val))))
+        .arg(Arg::with_name("peer-timeout")
+            .help("Seconds without any message from the other side, after which it is considered dead and all connections are closed.")
+            .long("peer-timeout")
+            .default_value("60")
+            .validator(|val| val.parse::<u64>().map(|_| ()).map_err(|_| format!("Cannot parse {} to u64", val))))
+        .arg(Arg::with_name("gc-age")
+            .help("Tunnel files not modified for this many seconds are removed by gc, the server and the client remove them in the background too.")
+            .long("gc-age")
+            .default_value("3600")
+            .validator(|val| val.parse::<u64>().map(|_| ()).map_err(|_| format!("Cannot parse {} to u64", val))))
+        .arg(Arg::with_name("dry-run")
+            .help("Only list the files gc would remove.")
+            .long("dry-run"))
+        .arg(Arg::with_name("no-gc")
+            .help("Do not remove the stale tunnel files in the background.")
+            .long("no-gc"))
+        .arg(Arg::with_name("all-sessions")
+            .help("Client mode only, serve all the sessions with a running server, new sessions are found while running.")
+            .long("all-sessions")
+            .conflicts_with("session"))
         .get_matches();
     let _ = log4rs::init_file(&matches.value_of("log-config").unwrap(), Default::default()).unwrap();
     let _ = s3run(&matches).unwrap();
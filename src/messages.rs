@@ -2,21 +2,75 @@
  * File: src/messages.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 07.09.2017
- * Last Modified Date: 11.10.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 
+use config::ForwardRule;
+use std::fmt;
+
+/// Address to listen on or to connect to.
+#[derive (Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Endpoint {
+    /// Tcp host and port.
+    Tcp(String, u16),
+    /// Path of the unix domain socket.
+    Unix(String),
+}
+
+impl Endpoint {
+    /// Parse `unix:/path/to/socket` or `host:port`.
+    pub fn parse(address: &str) -> Result<Endpoint, String> {
+        if address.starts_with("unix:") {
+            Ok(Endpoint::Unix(address["unix:".len()..].to_owned()))
+        } else {
+            address
+                .rfind(':')
+                .ok_or(format!("Missing port in {}", address))
+                .and_then(|pos| {
+                    address[pos + 1..]
+                        .parse::<u16>()
+                        .map(|port| {
+                            // ipv6 address is in brackets, e.g. [::1]:22
+                            let host = address[..pos].trim_matches(&['[', ']'][..]);
+                            Endpoint::Tcp(host.to_owned(), port)
+                        })
+                        .map_err(|_| format!("Cannot parse port in {}", address))
+                })
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Endpoint::Tcp(ref host, port) => write!(f, "{}:{}", host, port),
+            Endpoint::Unix(ref path)      => write!(f, "unix:{}", path),
+        }
+    }
+}
+
 /// Message payload
 #[derive (Debug, Serialize, Deserialize)]
 pub enum Payload {
     /// When there is a new connection to the server, it will send 'Connect' request with a new id.
-    Connect(u64,String,u16),
+    Connect(u64, Endpoint),
     Disconnect(u64),
     /// Pass data for specific connection.
     Data(u64, Vec<u8>),
     /// Send information which last message the other side has received. Sender can stop sending
     /// that through the tunnel.
     Sync(u64, usize),
+    /// Ask the other side to listen on these rules, previous rules which are not in the list are
+    /// stopped.
+    Listen(Vec<ForwardRule>),
+    /// Datagram of the udp flow with id, with the destination address when it goes to the side
+    /// which sends it out and the source address on the way back.
+    Datagram(u64, String, u16, Vec<u8>),
+    /// Sent periodically to let the other side know this side is alive, with the epoch of this
+    /// tunnel instance. New epoch means the other side has been restarted and counts the messages
+    /// from the beginning.
+    Keepalive(u64),
 }
 
 /// Message in the tunnel file
@@ -0,0 +1,253 @@
+/**
+ * File: src/multi.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::collections::HashMap;
+use std::io::{self};
+use std::time::{Duration, Instant};
+
+use config::{MultiConfig, MultiMode};
+use storage::{Storage, Object, Fetch};
+use envelope;
+
+/// How often the backup tunnel apis are checked for the file of the other side in the failover
+/// mode, in milliseconds.
+const BACKUP_POLL: u64 = 1000;
+
+/// One tunnel api of the multi storage.
+struct Path {
+    api: String,
+    storage: Box<dyn Storage>,
+    /// Failures in a row.
+    failures: u32,
+    /// After too many failures the path is not used until then.
+    down_until: Option<Instant>,
+    /// Versions of the read files in this path.
+    versions: HashMap<String, Option<String>>,
+    /// When the read files have been checked last time, only for the backup paths.
+    checked: HashMap<String, Instant>,
+}
+
+impl Path {
+    fn is_up(&self) -> bool {
+        self.down_until.map(|until| Instant::now() >= until).unwrap_or(true)
+    }
+}
+
+/// Last content of the read file given to the tunnel.
+struct Delivered {
+    generation: Option<u64>,
+    checksum: u64,
+}
+
+/// Storage over more tunnel apis. In the failover mode everything goes through the first one
+/// which works, the other side can be already using a backup one, so they are read too, just
+/// not so often. In the all mode everything is written to all of them and read from all of them.
+/// The same content read again from another path, or an older one, is not given to the tunnel.
+pub struct MultiStorage {
+    cfg: MultiConfig,
+    paths: Vec<Path>,
+    delivered: HashMap<String, Delivered>,
+}
+
+impl MultiStorage {
+    /// Count the result of the path, after too many failures in a row the path is not used for
+    /// a while.
+    fn record<T>(&mut self, index: usize, operation: &str, result: io::Result<T>) -> io::Result<T> {
+        let path = &mut self.paths[index];
+        match result {
+            Ok(value) => {
+                if path.down_until.take().is_some() {
+                    info!("Tunnel api {} works again", path.api);
+                }
+                path.failures = 0;
+                Ok(value)
+            }
+            Err(e) => {
+                path.failures += 1;
+                warn!("{} through {} failed: {}", operation, path.api, e);
+                if path.failures >= self.cfg.failures {
+                    warn!("Not using {} for {}s after {} failures", path.api, self.cfg.retry, path.failures);
+                    path.down_until = Some(Instant::now() + Duration::from_secs(self.cfg.retry));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Paths to use in the order of the config, without the failing ones. When all of them are
+    /// failing, all are tried.
+    fn usable(&self) -> Vec<usize> {
+        let up = (0..self.paths.len()).filter(|&index| self.paths[index].is_up()).collect::<Vec<_>>();
+        if up.is_empty() { (0..self.paths.len()).collect() } else { up }
+    }
+
+    /// Run the operation on all the usable paths, or only until it succeeds. It fails only when
+    /// it fails on all of them.
+    fn each<T, F: FnMut(&mut dyn Storage) -> io::Result<T>>(&mut self, operation: &str, all: bool, mut run: F) -> io::Result<Vec<T>> {
+        let mut done = Vec::new();
+        let mut error = None;
+        for index in self.usable() {
+            let result = run(&mut *self.paths[index].storage);
+            match self.record(index, operation, result) {
+                Ok(value) => {
+                    done.push(value);
+                    if !all {
+                        break;
+                    }
+                }
+                Err(e) => error = Some(e),
+            }
+        }
+        match error {
+            Some(e) if done.is_empty() => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    /// The same or an older content than the last one given to the tunnel is skipped.
+    fn is_new(&mut self, name: &str, data: &[u8]) -> bool {
+        let generation = envelope::open(data).ok().and_then(|(generation, _)| generation);
+        let checksum = envelope::checksum(data);
+        let new = match self.delivered.get(name) {
+            Some(last) if last.checksum == checksum => false,
+            Some(&Delivered { generation: Some(last), .. }) => generation.map(|generation| generation > last).unwrap_or(true),
+            _ => true,
+        };
+        if new {
+            self.delivered.insert(name.to_owned(), Delivered { generation, checksum });
+        }
+        new
+    }
+}
+
+impl Storage for MultiStorage {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let all = self.cfg.mode == MultiMode::All;
+        self.each(&format!("Writing {}", name), all, |storage| storage.put(name, data))
+            .map(|_| ())
+    }
+
+    /// The file can be in any of the paths.
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.each(&format!("Reading {}", name), true, |storage| storage.get(name))
+            .map(|found| found.into_iter().flatten().next())
+    }
+
+    /// The file is removed from all the paths, so an old one doesn't come back.
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        self.each(&format!("Removing {}", name), true, |storage| storage.delete(name))
+            .map(|_| ())
+    }
+
+    /// Files of all the paths, with the latest modification time.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let mut objects: HashMap<String, Option<u64>> = HashMap::new();
+        for found in self.each("Listing", true, |storage| storage.list(prefix))? {
+            for object in found {
+                let modified = objects.entry(object.name).or_insert(object.modified);
+                *modified = (*modified).max(object.modified);
+            }
+        }
+        Ok(objects.into_iter()
+            .map(|(name, modified)| Object { name, modified })
+            .collect())
+    }
+
+    /// The file is read from the paths one by one, the first new content is returned, the other
+    /// paths are read in the next call.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        if version.is_none() && self.delivered.remove(name).is_some() {
+            // the tunnel wants the file again, e.g. it was broken
+            for path in &mut self.paths {
+                path.versions.remove(name);
+            }
+        }
+        let usable = self.usable();
+        let backup = Duration::from_millis(BACKUP_POLL);
+        let (mut missing, mut failed, mut error) = (true, 0, None);
+        for (i, &index) in usable.iter().enumerate() {
+            if self.cfg.mode == MultiMode::Failover && i > 0 {
+                let path = &mut self.paths[index];
+                if path.checked.get(name).map(|last| last.elapsed() < backup).unwrap_or(false) {
+                    if path.versions.get(name).map(|version| version.is_some()).unwrap_or(false) {
+                        missing = false;
+                    }
+                    continue;
+                }
+                path.checked.insert(name.to_owned(), Instant::now());
+            }
+            let result = {
+                let path = &mut self.paths[index];
+                let version = path.versions.entry(name.to_owned()).or_insert(None);
+                let result = path.storage.get_changed(name, version);
+                if version.is_some() {
+                    missing = false;
+                }
+                result
+            };
+            match self.record(index, &format!("Reading {}", name), result) {
+                Ok(Fetch::Changed(data)) => {
+                    if self.is_new(name, &data) {
+                        *version = Some(format!("{:016x}", envelope::checksum(&data)));
+                        return Ok(Fetch::Changed(data));
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => {
+                    failed += 1;
+                    error = Some(e);
+                }
+            }
+        }
+        match error {
+            Some(e) if failed == usable.len() => Err(e),
+            _ if missing && failed == 0 => {
+                self.delivered.remove(name);
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+            _ => Ok(Fetch::Unchanged),
+        }
+    }
+}
+
+/// Create the storages of all the tunnel apis, the ones which can't be created are left out.
+pub fn create_storage(cfg: MultiConfig, create: fn(&str) -> io::Result<Box<dyn Storage>>) -> io::Result<Box<dyn Storage>> {
+    if cfg.apis.is_empty() || cfg.apis.iter().any(|api| api.split(':').next() == Some("multi")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the multi section needs a list of the other tunnel apis"));
+    }
+    let mut paths = Vec::new();
+    let mut error = None;
+    for api in &cfg.apis {
+        match create(api) {
+            Ok(storage) => {
+                info!("Using {} tunnel api in {:?} mode", api, cfg.mode);
+                paths.push(Path {
+                    api: api.clone(),
+                    storage,
+                    failures: 0,
+                    down_until: None,
+                    versions: HashMap::new(),
+                    checked: HashMap::new(),
+                });
+            }
+            Err(e) => {
+                error!("Failed to create {} tunnel api: {}", api, e);
+                error = Some(e);
+            }
+        }
+    }
+    match error {
+        Some(e) if paths.is_empty() => Err(e),
+        _ => Ok(Box::new(MultiStorage {
+            cfg,
+            paths,
+            delivered: HashMap::new(),
+        })),
+    }
+}
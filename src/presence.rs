@@ -0,0 +1,95 @@
+/**
+ * File: src/presence.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self};
+use std::time::Duration;
+use serde_yaml;
+
+use storage::{Storage, Periodic};
+use tools::{hostname, now_secs};
+
+/// Directory of the presence objects.
+const PEERS: &str = "peers/";
+
+/// How often the presence object is written.
+pub const HEARTBEAT_INTERVAL: u64 = 10;
+
+/// Peer which has not written its presence for this many seconds is considered gone.
+const PEER_GONE: u64 = 3 * HEARTBEAT_INTERVAL;
+
+/// Features this version supports.
+const FEATURES: [&str; 8] = ["forwards", "reverse", "socks", "udp", "unix", "stdio", "sessions", "all-sessions"];
+
+/// Presence of one running endpoint, it is written periodically while the endpoint runs.
+#[derive(Debug, Serialize, Deserialize)]
+struct Presence {
+    hostname: String,
+    pid: u32,
+    mode: String,
+    session: Option<String>,
+    version: String,
+    features: Vec<String>,
+    started: u64,
+    last_seen: u64,
+}
+
+/// Keeps the presence object of this process fresh.
+pub fn heartbeat(mode: &str, session: Option<&str>) -> Periodic {
+    use std::process;
+    let mut presence = Presence {
+        hostname: hostname(),
+        pid: process::id(),
+        mode: mode.to_owned(),
+        session: session.map(|s| s.to_owned()),
+        version: crate_version!().to_owned(),
+        features: FEATURES.iter().map(|f| f.to_string()).collect(),
+        started: now_secs(),
+        last_seen: 0,
+    };
+    Periodic {
+        name: format!("{}{}.{}.peer", PEERS, presence.hostname, presence.pid),
+        interval: Duration::from_secs(HEARTBEAT_INTERVAL),
+        content: Box::new(move || {
+            presence.last_seen = now_secs();
+            serde_yaml::to_string(&presence).unwrap().into_bytes()
+        }),
+    }
+}
+
+/// Print the endpoints which have written their presence.
+pub fn list(storage: &mut dyn Storage) -> io::Result<()> {
+    let mut peers = Vec::new();
+    for object in storage.list(PEERS)? {
+        let object = object.name;
+        if let Some(body) = storage.get(&object)? {
+            match serde_yaml::from_slice::<Presence>(&body) {
+                Ok(presence) => peers.push(presence),
+                Err(e) => warn!("Invalid presence object {}: {}", object, e),
+            }
+        }
+    }
+    if peers.is_empty() {
+        println!("No peers");
+    }
+    let now = now_secs();
+    peers.sort_by_key(|peer| now - peer.last_seen.min(now));
+    for peer in peers {
+        let seen = now.saturating_sub(peer.last_seen);
+        println!("{}:{} {} session '{}' version {} {}, seen {}s ago, up {}s, features: {}",
+                 peer.hostname,
+                 peer.pid,
+                 peer.mode,
+                 peer.session.unwrap_or_default(),
+                 peer.version,
+                 if seen < PEER_GONE { "alive" } else { "gone" },
+                 seen,
+                 peer.last_seen.saturating_sub(peer.started),
+                 peer.features.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,432 @@
+/**
+ * File: src/redis.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use config::RedisConfig;
+use storage::{Storage, Object, Fetch, Watched};
+use tools::now_secs;
+use envelope;
+
+/// How long one blocking pop waits for a change of the watched file, in seconds.
+const WAIT: u64 = 5;
+
+/// How long the change notifications are kept, when nobody reads them.
+const NOTIFY_EXPIRE: &str = "600";
+
+/// Reply of the redis server.
+enum Reply {
+    Status,
+    Integer,
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+impl Reply {
+    fn into_bulk(self) -> Option<Vec<u8>> {
+        match self {
+            Reply::Bulk(bulk) => bulk,
+            _ => None,
+        }
+    }
+
+    fn into_array(self) -> Vec<Reply> {
+        match self {
+            Reply::Array(Some(array)) => array,
+            _ => Vec::new(),
+        }
+    }
+}
+
+trait Stream: Read + Write + Send {}
+impl<S: Read + Write + Send> Stream for S {}
+
+/// Connection to the redis server, speaking the plain RESP protocol.
+struct Connection {
+    reader: BufReader<Box<dyn Stream>>,
+}
+
+impl Connection {
+    fn open(cfg: &RedisConfig, timeout: Duration) -> io::Result<Connection> {
+        let stream = TcpStream::connect(&cfg.address[..])?;
+        stream.set_read_timeout(Some(timeout))?;
+        // the commands are small, they shouldn't wait for each other
+        stream.set_nodelay(true)?;
+        let stream: Box<dyn Stream> = Box::new(stream);
+        let mut connection = Connection {
+            reader: BufReader::new(stream),
+        };
+        if let Some(ref password) = cfg.password {
+            connection.call(&[b"AUTH", password.as_bytes()])?;
+        }
+        if cfg.db != 0 {
+            connection.call(&[b"SELECT", cfg.db.to_string().as_bytes()])?;
+        }
+        Ok(connection)
+    }
+
+    fn send(&mut self, args: &[&[u8]]) -> io::Result<()> {
+        let mut request = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+            request.extend_from_slice(arg);
+            request.extend_from_slice(b"\r\n");
+        }
+        self.reader.get_mut().write_all(&request)
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+        }
+        Ok(line.trim_end_matches("\r\n").to_owned())
+    }
+
+    fn read_reply(&mut self) -> io::Result<Reply> {
+        let line = self.read_line()?;
+        if line.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty reply"));
+        }
+        let (kind, rest) = line.split_at(1);
+        let number = || io_res!(rest.parse::<i64>(), InvalidData);
+        match kind {
+            "+" => Ok(Reply::Status),
+            "-" => Err(io::Error::new(io::ErrorKind::Other, format!("redis: {}", rest))),
+            ":" => number().map(|_| Reply::Integer),
+            "$" => {
+                let len = number()?;
+                if len < 0 {
+                    return Ok(Reply::Bulk(None));
+                }
+                let mut bulk = vec![0; len as usize + 2];
+                self.reader.read_exact(&mut bulk)?;
+                bulk.truncate(len as usize);
+                Ok(Reply::Bulk(Some(bulk)))
+            }
+            "*" => {
+                let len = number()?;
+                if len < 0 {
+                    return Ok(Reply::Array(None));
+                }
+                let mut array = Vec::new();
+                for _ in 0..len {
+                    array.push(self.read_reply()?);
+                }
+                Ok(Reply::Array(Some(array)))
+            }
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid reply '{}'", line))),
+        }
+    }
+
+    fn call(&mut self, args: &[&[u8]]) -> io::Result<Reply> {
+        self.send(args)?;
+        self.read_reply()
+    }
+
+    /// Send all the commands at once and read all the replies.
+    fn pipeline(&mut self, commands: &[Vec<&[u8]>]) -> io::Result<Vec<Reply>> {
+        for args in commands {
+            self.send(args)?;
+        }
+        commands.iter().map(|_| self.read_reply()).collect()
+    }
+}
+
+/// Storage in a redis server. Every file is a hash with its data and modification time, every
+/// change is announced in a notification list of the file, where the other side waits with
+/// a blocking pop, so the change arrives right away without asking the server all the time.
+pub struct RedisStorage {
+    cfg: RedisConfig,
+    /// Opened on the first use and after any failure.
+    connection: Option<Connection>,
+    watched: HashMap<String, Arc<Mutex<Watched>>>,
+    closed: Arc<AtomicBool>,
+}
+
+fn file_key(cfg: &RedisConfig, name: &str) -> String {
+    format!("{}file:{}", cfg.prefix, name)
+}
+
+fn notify_key(cfg: &RedisConfig, name: &str) -> String {
+    format!("{}notify:{}", cfg.prefix, name)
+}
+
+/// Escape the special characters of the SCAN pattern.
+fn escape_pattern(pattern: &str) -> String {
+    pattern.chars()
+        .flat_map(|c| match c {
+            '*' | '?' | '[' | ']' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+/// State of the file from its data, the version is the checksum of the data.
+fn watched(data: Option<Vec<u8>>) -> Watched {
+    match data {
+        Some(data) => Watched::Object(format!("{:016x}", envelope::checksum(&data)), data),
+        None => Watched::Missing,
+    }
+}
+
+/// Read the file.
+fn read_watched(connection: &mut Connection, cfg: &RedisConfig, name: &str) -> io::Result<Watched> {
+    let key = file_key(cfg, name);
+    Ok(watched(connection.call(&[b"HGET", key.as_bytes(), b"data"])?.into_bulk()))
+}
+
+/// Keep the state of the file up to date, until the storage is dropped.
+fn watch(cfg: RedisConfig, name: String, state: Arc<Mutex<Watched>>, closed: Arc<AtomicBool>) {
+    let mut connection = None;
+    let notify = notify_key(&cfg, &name);
+    while !closed.load(Ordering::SeqCst) {
+        if connection.is_none() {
+            match Connection::open(&cfg, Duration::from_secs(WAIT + 10)) {
+                Ok(opened) => connection = Some(opened),
+                Err(e) => {
+                    *state.lock().unwrap() = Watched::Failed(e.to_string());
+                    thread::sleep(Duration::from_secs(1));
+                    continue;
+                }
+            }
+        }
+        // the file is read also after the timeout, in case the notification got lost
+        let result = connection.as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "not connected"))
+            .and_then(|connection| {
+                connection.call(&[b"BLPOP", notify.as_bytes(), WAIT.to_string().as_bytes()])?;
+                read_watched(connection, &cfg, &name)
+            });
+        match result {
+            Ok(watched) => *state.lock().unwrap() = watched,
+            Err(e) => {
+                *state.lock().unwrap() = Watched::Failed(e.to_string());
+                connection = None;
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+impl RedisStorage {
+    /// Run the commands on the connection, which is opened again after a failure.
+    fn pipeline(&mut self, commands: &[Vec<&[u8]>]) -> io::Result<Vec<Reply>> {
+        if self.connection.is_none() {
+            self.connection = Some(Connection::open(&self.cfg, Duration::from_secs(30))?);
+        }
+        let result = self.connection.as_mut().unwrap().pipeline(commands);
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+
+    /// Let the watching side know the file has changed.
+    fn notify_commands(notify: &str) -> Vec<Vec<&[u8]>> {
+        vec![
+            vec![b"RPUSH", notify.as_bytes(), b"1"],
+            vec![b"LTRIM", notify.as_bytes(), b"-1", b"-1"],
+            vec![b"EXPIRE", notify.as_bytes(), NOTIFY_EXPIRE.as_bytes()],
+        ]
+    }
+}
+
+impl Storage for RedisStorage {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let (key, notify, modified) = (file_key(&self.cfg, name), notify_key(&self.cfg, name), now_secs().to_string());
+        let mut commands = vec![vec![b"HSET" as &[u8], key.as_bytes(), b"data", data, b"modified", modified.as_bytes()]];
+        commands.extend(RedisStorage::notify_commands(&notify));
+        self.pipeline(&commands).map(|_| ())
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        let key = file_key(&self.cfg, name);
+        let mut replies = self.pipeline(&[vec![b"HGET", key.as_bytes(), b"data"]])?;
+        Ok(replies.remove(0).into_bulk())
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        let (key, notify) = (file_key(&self.cfg, name), notify_key(&self.cfg, name));
+        let mut commands = vec![vec![b"DEL" as &[u8], key.as_bytes()]];
+        commands.extend(RedisStorage::notify_commands(&notify));
+        self.pipeline(&commands).map(|_| ())
+    }
+
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let base = file_key(&self.cfg, "");
+        let pattern = format!("{}*", escape_pattern(&file_key(&self.cfg, prefix)));
+        let mut cursor = "0".to_owned();
+        let mut keys = Vec::new();
+        loop {
+            let mut replies = self.pipeline(&[vec![b"SCAN", cursor.as_bytes(), b"MATCH", pattern.as_bytes(), b"COUNT", b"100"]])?;
+            let mut scan = replies.remove(0).into_array().into_iter();
+            cursor = scan.next().and_then(|cursor| cursor.into_bulk()).map(|cursor| String::from_utf8_lossy(&cursor).into_owned()).unwrap_or_default();
+            let found = scan.next().map(|found| found.into_array()).unwrap_or_default();
+            keys.extend(found.into_iter().filter_map(|key| key.into_bulk()).map(|key| String::from_utf8_lossy(&key).into_owned()));
+            if cursor == "0" || cursor.is_empty() {
+                break;
+            }
+        }
+        // scan can return the same key more times
+        keys.sort();
+        keys.dedup();
+        let commands = keys.iter().map(|key| vec![b"HGET" as &[u8], key.as_bytes(), b"modified"]).collect::<Vec<_>>();
+        let modified = self.pipeline(&commands)?;
+        Ok(keys.iter()
+            .zip(modified)
+            .filter_map(|(key, modified)| {
+                Some(Object {
+                    name: key.strip_prefix(&base)?.to_owned(),
+                    modified: modified.into_bulk().and_then(|time| String::from_utf8_lossy(&time).parse().ok()),
+                })
+            })
+            .collect())
+    }
+
+    /// The first read starts the watching thread, then only its last state is checked.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        if !self.watched.contains_key(name) {
+            let first = self.get(name)?;
+            let state = Arc::new(Mutex::new(watched(first)));
+            let (cfg, name_copy, state_copy, closed) = (self.cfg.clone(), name.to_owned(), state.clone(), self.closed.clone());
+            thread::spawn(move || watch(cfg, name_copy, state_copy, closed));
+            self.watched.insert(name.to_owned(), state);
+        }
+        let state = self.watched[name].lock().unwrap();
+        state.fetch(version)
+    }
+}
+
+impl Drop for RedisStorage {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn create_storage(cfg: RedisConfig) -> io::Result<Box<dyn Storage>> {
+    Ok(Box::new(RedisStorage {
+        cfg,
+        connection: None,
+        watched: HashMap::new(),
+        closed: Arc::new(AtomicBool::new(false)),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Server with the replies prepared, what the client writes is kept.
+    struct Script {
+        replies: io::Cursor<Vec<u8>>,
+        sent: Sent,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.sent.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What the client has written to the script.
+    type Sent = Arc<Mutex<Vec<u8>>>;
+
+    fn script(replies: &str) -> (Connection, Sent) {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let stream: Box<dyn Stream> = Box::new(Script {
+            replies: io::Cursor::new(replies.replace('\n', "\r\n").into_bytes()),
+            sent: sent.clone(),
+        });
+        (Connection { reader: BufReader::new(stream) }, sent)
+    }
+
+    fn sent(sent: &Sent) -> String {
+        String::from_utf8_lossy(&sent.lock().unwrap()).replace("\r\n", "\n")
+    }
+
+    fn storage(connection: Connection) -> RedisStorage {
+        RedisStorage {
+            cfg: RedisConfig {
+                // nothing listens there, so a new connection fails
+                address: "127.0.0.1:1".to_owned(),
+                password: None,
+                db: 0,
+                prefix: "t:".to_owned(),
+            },
+            connection: Some(connection),
+            watched: HashMap::new(),
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    #[test]
+    fn replies() {
+        let (mut connection, _) = script("+OK\n:42\n$6\nab\ncd\n$-1\n*2\n$1\na\n*-1\n-ERR wrong\n?\n");
+        assert!(matches!(connection.read_reply().unwrap(), Reply::Status));
+        assert!(matches!(connection.read_reply().unwrap(), Reply::Integer));
+        assert_eq!(connection.read_reply().unwrap().into_bulk(), Some(b"ab\r\ncd".to_vec()));
+        assert!(matches!(connection.read_reply().unwrap(), Reply::Bulk(None)));
+        let mut array = connection.read_reply().unwrap().into_array().into_iter();
+        assert_eq!(array.next().unwrap().into_bulk(), Some(b"a".to_vec()));
+        assert!(matches!(array.next().unwrap(), Reply::Array(None)));
+        assert_eq!(connection.read_reply().err().unwrap().to_string(), "redis: ERR wrong");
+        assert_eq!(connection.read_reply().err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(connection.read_reply().err().unwrap().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn pipeline_sends_all_first() {
+        let (mut connection, written) = script(":1\n$1\nx\n");
+        let replies = connection.pipeline(&[vec![b"RPUSH", b"key", b"1"], vec![b"GET", b"a b"]]).unwrap();
+        assert_eq!(replies.len(), 2);
+        assert_eq!(sent(&written), "*3\n$5\nRPUSH\n$3\nkey\n$1\n1\n*2\n$3\nGET\n$3\na b\n");
+    }
+
+    #[test]
+    fn list_scans_until_cursor_is_zero() {
+        let (connection, written) = script(concat!(
+            "*2\n$2\n17\n*2\n$8\nt:file:b\n$8\nt:file:a\n",
+            "*2\n$1\n0\n*2\n$8\nt:file:b\n$8\nt:file:c\n",
+            "$2\n10\n$-1\n$2\n30\n"));
+        let mut storage = storage(connection);
+        let objects = storage.list("").unwrap();
+        assert_eq!(objects.iter().map(|o| (o.name.as_str(), o.modified)).collect::<Vec<_>>(),
+            vec![("a", Some(10)), ("b", None), ("c", Some(30))]);
+        let written = sent(&written);
+        assert!(written.contains("SCAN\n$1\n0\n"));
+        assert!(written.contains("SCAN\n$2\n17\n"));
+        assert_eq!(written.matches("HGET").count(), 3);
+    }
+
+    #[test]
+    fn failed_read_drops_connection() {
+        let (connection, _) = script("");
+        let mut storage = storage(connection);
+        assert!(storage.get_changed("file", &mut None).is_err());
+        assert!(storage.connection.is_none());
+        assert!(storage.watched.is_empty());
+    }
+}
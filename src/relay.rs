@@ -0,0 +1,162 @@
+/**
+ * File: src/relay.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+use hyper::Client;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use hyper::header::{Authorization, Bearer, ETag};
+
+use config::RelayConfig;
+use storage::{Storage, Object, Fetch, Watched};
+
+/// How long the relay holds one read of the tunnel file, when nothing changes.
+const WAIT: u64 = 20;
+
+/// Storage on the tunnel-relay server. The tunnel file of the other side is watched by a thread
+/// with a waiting read, so a change arrives right away and the relay is not asked all the time.
+pub struct RelayStorage {
+    cfg: RelayConfig,
+    client: Client,
+    watched: HashMap<String, Arc<Mutex<Watched>>>,
+    closed: Arc<AtomicBool>,
+}
+
+/// Send one request to the relay, returns the status, the version and the body.
+fn request(client: &Client, cfg: &RelayConfig, method: Method, path: &str, body: Option<&[u8]>) -> io::Result<(StatusCode, Option<String>, Vec<u8>)> {
+    let url = format!("{}/{}", cfg.url.trim_end_matches('/'), path);
+    let send = || {
+        let mut builder = client.request(method.clone(), &url);
+        if let Some(ref token) = cfg.token {
+            builder = builder.header(Authorization(Bearer { token: token.clone() }));
+        }
+        if let Some(body) = body {
+            builder = builder.body(body);
+        }
+        builder.send()
+    };
+    // the kept alive connection can be already closed by the relay, all the requests can be
+    // repeated, so just try once more
+    let mut response = match send() {
+        Ok(response) => response,
+        Err(e) => {
+            info!("Repeating {} {}: {}", method, url, e);
+            io_res!(send())?
+        }
+    };
+    let mut data = Vec::new();
+    response.read_to_end(&mut data)?;
+    let version = response.headers.get::<ETag>().map(|tag| tag.tag().to_owned());
+    match response.status {
+        StatusCode::Ok | StatusCode::NoContent | StatusCode::NotModified | StatusCode::NotFound => Ok((response.status, version, data)),
+        status => Err(io::Error::new(io::ErrorKind::Other, format!("{} {}: {}", url, status, String::from_utf8_lossy(&data).trim()))),
+    }
+}
+
+/// Read the object, if its version is not `version` anymore, or after the wait.
+fn fetch(client: &Client, cfg: &RelayConfig, name: &str, version: &str, wait: u64) -> io::Result<Option<Watched>> {
+    let path = format!("objects/{}?version={}&wait={}", name, version, wait);
+    match request(client, cfg, Method::Get, &path, None)? {
+        (StatusCode::Ok, Some(version), data) => Ok(Some(Watched::Object(version, data))),
+        (StatusCode::NotFound, _, _) => Ok(Some(Watched::Missing)),
+        (StatusCode::NotModified, _, _) => Ok(None),
+        (status, _, _) => Err(io::Error::new(io::ErrorKind::Other, format!("unexpected response {} for {}", status, name))),
+    }
+}
+
+/// Keep the state of the object up to date, until the storage is dropped.
+fn watch(cfg: RelayConfig, name: String, state: Arc<Mutex<Watched>>, closed: Arc<AtomicBool>) {
+    let mut client = Client::new();
+    client.set_read_timeout(Some(Duration::from_secs(WAIT + 10)));
+    while !closed.load(Ordering::SeqCst) {
+        let version = match *state.lock().unwrap() {
+            Watched::Object(ref version, _) => version.clone(),
+            Watched::Missing => "none".to_owned(),
+            // the next successful read replaces the error
+            Watched::Failed(_) => "failed".to_owned(),
+        };
+        match fetch(&client, &cfg, &name, &version, WAIT) {
+            Ok(Some(watched)) => *state.lock().unwrap() = watched,
+            Ok(None) => (),
+            Err(e) => {
+                *state.lock().unwrap() = Watched::Failed(e.to_string());
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+impl Storage for RelayStorage {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        request(&self.client, &self.cfg, Method::Put, &format!("objects/{}", name), Some(data))
+            .map(|_| ())
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match fetch(&self.client, &self.cfg, name, "", 0)? {
+            Some(Watched::Object(_, data)) => Ok(Some(data)),
+            _ => Ok(None),
+        }
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        request(&self.client, &self.cfg, Method::Delete, &format!("objects/{}", name), None)
+            .map(|_| ())
+    }
+
+    /// The relay prints one object per line, the modification time and the name.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let (_, _, data) = request(&self.client, &self.cfg, Method::Get, &format!("list/{}", prefix), None)?;
+        Ok(String::from_utf8_lossy(&data)
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ' ');
+                match (parts.next(), parts.next()) {
+                    (Some(modified), Some(name)) => Some(Object {
+                        name: name.to_owned(),
+                        modified: modified.parse().ok(),
+                    }),
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+
+    /// The first read starts the watching thread, then only its last state is checked.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        if !self.watched.contains_key(name) {
+            let first = fetch(&self.client, &self.cfg, name, "", 0)?.unwrap_or(Watched::Missing);
+            let state = Arc::new(Mutex::new(first));
+            let (cfg, name_copy, state_copy, closed) = (self.cfg.clone(), name.to_owned(), state.clone(), self.closed.clone());
+            thread::spawn(move || watch(cfg, name_copy, state_copy, closed));
+            self.watched.insert(name.to_owned(), state);
+        }
+        let state = self.watched[name].lock().unwrap();
+        state.fetch(version)
+    }
+}
+
+impl Drop for RelayStorage {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+    }
+}
+
+pub fn create_storage(cfg: RelayConfig) -> io::Result<Box<dyn Storage>> {
+    Ok(Box::new(RelayStorage {
+        cfg,
+        client: Client::new(),
+        watched: HashMap::new(),
+        closed: Arc::new(AtomicBool::new(false)),
+    }))
+}
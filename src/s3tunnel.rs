@@ -2,43 +2,137 @@
  * File: src/s3tunnel.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 07.09.2017
- * Last Modified Date: 11.10.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 
 use std::io::{self};
 use config::S3Config;
-use serde_yaml;
 
-use std::sync::mpsc::{channel};
+use storage::{Storage, Object, Fetch};
+use tools::parse_time;
+use aws_sdk_rust::aws::common::credentials::DefaultCredentialsProvider;
+use aws_sdk_rust::aws::common::request::DispatchSignedRequest;
+use aws_sdk_rust::aws::s3::s3client::S3Client;
+use aws_sdk_rust::aws::s3::object::{ GetObjectOutput, GetObjectRequest, HeadObjectRequest, PutObjectRequest, DeleteObjectRequest, ListObjectsRequest};
 
-use tunnel::*;
-use messages::*;
-use aws_sdk_rust::aws::s3::object::{ GetObjectRequest, PutObjectRequest};
+/// Storage in the s3 bucket using the aws library.
+pub struct S3Storage<D: DispatchSignedRequest> {
+    client: S3Client<DefaultCredentialsProvider, D>,
+    bucket_name: String,
+    bucket_prefix: String,
+}
+
+impl<D: DispatchSignedRequest> S3Storage<D> {
+    fn key(&self, name: &str) -> String {
+        format!("{}/{}", self.bucket_prefix, name)
+    }
+
+    /// Read the object with its headers, None if the object doesn't exist.
+    fn get_object(&mut self, name: &str) -> io::Result<Option<GetObjectOutput>> {
+        let mut request = GetObjectRequest::default();
+        request.bucket = self.bucket_name.clone();
+        request.key = self.key(name);
+        match self.client.get_object(&request, None) {
+            Ok(output) => Ok(Some(output)),
+            Err(ref e) if e.aws.code == "NoSuchKey" => Ok(None),
+            Err(e) => io_res!(Err(e)),
+        }
+    }
+}
 
-macro_rules! delete_files {
-    ($client: ident, $bucket_name: ident, $bucket_prefix: ident, $files: expr) => {
-        use aws_sdk_rust::aws::s3::object::{ DeleteObjectRequest};
-        let mut del = DeleteObjectRequest::default();
-        del.bucket = $bucket_name.clone();
-        for file in $files.iter() {
-            del.key = format!("{}/{}", $bucket_prefix, file);
-            match $client.delete_object(&del, None) {
-                Ok(_) => info!( "Tunnel file {} is removed", file),
-                Err(e) => error!("{:#?}", e),
+impl<D: DispatchSignedRequest + Send> Storage for S3Storage<D> {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut request = PutObjectRequest::default();
+        request.bucket = self.bucket_name.clone();
+        request.key = self.key(name);
+        request.body = Some(data);
+        io_res!(self.client.put_object(&request, None))
+            .map(|_| ())
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.get_object(name).map(|output| output.map(|output| output.body))
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        let mut request = DeleteObjectRequest::default();
+        request.bucket = self.bucket_name.clone();
+        request.key = self.key(name);
+        io_res!(self.client.delete_object(&request, None))
+            .map(|_| ())
+    }
+
+    /// The library doesn't send If-None-Match, so the ETag is checked by HEAD first, which
+    /// doesn't transfer the object. The object can change between HEAD and GET, so the version
+    /// is the ETag of the GET.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        let mut request = HeadObjectRequest::default();
+        request.bucket = self.bucket_name.clone();
+        request.key = self.key(name);
+        let e_tag = match self.client.head_object(&request) {
+            Ok(output) => output.e_tag,
+            // HEAD has no body, so the error code is missing
+            Err(ref e) if e.message.contains("404") => {
+                *version = None;
+                return Ok(Fetch::Missing);
             }
+            Err(e) => return io_res!(Err(e)),
+        };
+        if version.as_ref() == Some(&e_tag) {
+            return Ok(Fetch::Unchanged);
         }
+        match self.get_object(name)? {
+            Some(output) => {
+                *version = Some(output.e_tag);
+                Ok(Fetch::Changed(output.body))
+            }
+            None => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+        }
+    }
+
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let mut request = ListObjectsRequest::default();
+        request.bucket = self.bucket_name.clone();
+        // the library doesn't pass the prefix to s3, so the objects are filtered here
+        let prefix = self.key(prefix);
+        io_res!(self.client.list_objects(&request))
+            .map(|output| {
+                if output.is_truncated {
+                    warn!("Too many objects in the bucket {}, only the first {} are listed", self.bucket_name, output.contents.len());
+                }
+                output.contents
+                    .into_iter()
+                    .filter(|object| object.key.starts_with(&prefix))
+                    .map(|object| {
+                        // last modified is like 2017-10-11T12:34:56.000Z
+                        let mut time = object.last_modified.trim_end_matches('Z').split('T');
+                        let modified = match (time.next(), time.next()) {
+                            (Some(date), Some(time)) => parse_time(date, time),
+                            _ => None,
+                        };
+                        Object {
+                            name: object.key[self.bucket_prefix.len() + 1..].to_owned(),
+                            modified,
+                        }
+                    })
+                    .collect()
+            })
     }
 }
 
-pub fn create_clients(is_server: bool, cfg: S3Config, writer_name: &str, reader_name: &str) -> io::Result<TunnelPipes> {
+/// Create connection to s3.
+pub fn create_storage(cfg: S3Config) -> io::Result<Box<dyn Storage>> {
     // create connection to s3
     let access_key = cfg.access_key;
     let bucket_name = cfg.bucket_name;
     let bucket_prefix = cfg.bucket_prefix;
     let bucket_location = cfg.bucket_location;
     let secret_key = cfg.secret_key;
-    use aws_sdk_rust::aws::common::credentials::{DefaultCredentialsProvider,ParametersProvider};
+    use aws_sdk_rust::aws::common::credentials::ParametersProvider;
     // Create s3 connection
     ParametersProvider::with_parameters(
         access_key,
@@ -48,12 +142,6 @@ pub fn create_clients(is_server: bool, cfg: S3Config, writer_name: &str, reader_
             // Credentials to connect to the s3 cloud
             info!("Creating credentials");
             DefaultCredentialsProvider::new(Some(credentials.clone()))
-                //.and_then(|provider| {
-                    //DefaultCredentialsProvider::new(Some(credentials))
-                        //.and_then(|provider2| {
-                            //Ok((provider, provider2))
-                        //})
-                //})
         })
         .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
         .and_then(|provider| {
@@ -68,11 +156,11 @@ pub fn create_clients(is_server: bool, cfg: S3Config, writer_name: &str, reader_
                     Ok(Endpoint::new(region, Signature::V4, None, None, None, None))
                 })
                 .and_then(|endpoint| {
-                    use aws_sdk_rust::aws::s3::s3client::S3Client;
-                    Ok((     S3Client::new(provider, endpoint)
-                            //,S3Client::new(provider2, endpoint)
-                            ,bucket_name
-                            ,bucket_prefix))
+                    Ok(Box::new(S3Storage {
+                        client: S3Client::new(provider, endpoint),
+                        bucket_name,
+                        bucket_prefix,
+                    }) as Box<dyn Storage>)
                 })
                 .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
         })
@@ -80,73 +168,4 @@ pub fn create_clients(is_server: bool, cfg: S3Config, writer_name: &str, reader_
             info!("S3 connection created");
             a
         })
-        .and_then(|(client, bucket_name, bucket_prefix)| {
-            let (writer_sender, writer_receiver) = channel::<WriteCommand>();
-            let (reader_sender, reader_receiver) = channel::<ReadCommand>();
-
-            use std::thread;
-            info!("Reading data from '{}'", reader_name);
-            info!("Writing data to '{}'", writer_name);
-            // create thread own variables to avoid lifetime errors
-            let reader_name: String = reader_name.into();
-            let writer_name: String = writer_name.into();
-
-            thread::spawn(move|| {
-                if is_server {
-                    delete_files!(client, bucket_name, bucket_prefix, vec![&reader_name, &writer_name]);
-                }
-                let mut reader = GetObjectRequest::default();
-                reader.bucket = bucket_name.clone();
-                reader.key = format!("{}/{}", bucket_prefix, reader_name);
-                loop {
-                    for cmd in writer_receiver.try_iter() {
-                        match cmd {
-                            WriteCommand::Write(msg) => {
-                                let mut stream_out = PutObjectRequest::default();
-                                stream_out.bucket = bucket_name.clone();
-                                stream_out.key = format!("{}/{}", bucket_prefix, writer_name);
-                                stream_out.body = Some(&msg);
-                                match client.put_object(&stream_out, None) {
-                                    Ok(_) => {
-                                        info!("Message writes, size = {}", msg.len());
-                                    }
-                                    Err(e) => error!("{:#?}", e),
-                                }
-                            }
-                            WriteCommand::Delete => {
-                                delete_files!(client, bucket_name, bucket_name, vec![&writer_name]);
-                            }
-                        }
-                    }
-
-                    match client.get_object(&reader, None) {
-                        Ok(output) => {
-                            use std::str;
-                            let text = str::from_utf8(&output.body).unwrap();
-                            let stream: Vec<Message> = serde_yaml::from_str(text).unwrap();
-
-                            let _ = reader_sender.send(ReadCommand::Read(stream)).unwrap();
-                            //sleep(Duration::from_millis(800));
-                        }
-                        Err(_) => {
-                            let _ = reader_sender.send(ReadCommand::NoFile).unwrap();
-                            // if the file is missing, just wait until it is created
-                            //info!("{} not found", reader_name);
-                            //sleep(Duration::from_millis(2000));
-                        }
-                    }
-
-                    wait_little!();
-                }
-            });
-
-
-            Ok(TunnelPipes{
-                writer: writer_sender,
-                reader: reader_receiver,
-            })
-        })
 }
-
-
-
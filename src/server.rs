@@ -2,105 +2,164 @@
  * File: src/server.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 07.09.2017
- * Last Modified Date: 11.10.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 
 
 use tunnel::{Tunnel, WriterData};
 use std::io::{self};
-use clap::ArgMatches;
-use connection::{manage_clients, run_connection};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
 use std::thread;
+use clap::ArgMatches;
+use connection::{manage_clients, connect};
+use config::{ForwardMode, ForwardRule, ForwardsConfig, load_forwards};
+use messages::Endpoint;
+use forward::Forwards;
+use udp::{manage_flows, new_flows};
+use shutdown;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
-use std::sync::{Arc, Mutex};
+/// Forward rules from the config file, if there are none, the rule is created from the command
+/// line arguments.
+fn load_rules(matches: &ArgMatches) -> io::Result<ForwardsConfig> {
+    load_forwards()
+        .map(|mut cfg| {
+            if cfg.forwards.is_empty() {
+                let port = matches.value_of("server-port").unwrap();
+                cfg.forwards.push(ForwardRule {
+                    name: "default".to_owned(),
+                    bind: format!("0.0.0.0:{}", port),
+                    mode: ForwardMode::Direct,
+                    host: matches.value_of("client-address").unwrap().to_owned(),
+                    port: value_t!(matches, "client-port", u16).unwrap(),
+                    path: None,
+                });
+            }
+            cfg
+        })
+}
 
-pub fn run(matches: &ArgMatches, tunnel: Tunnel) -> io::Result<()>{
-    use std::net::{TcpListener};
-    let port = &matches.value_of("server-port").unwrap();
-    let address = format!("0.0.0.0:{}",port);
-    info!("Creating server on: {}", address);
-    let client_ip = {
-        let client_ip = &matches.value_of("client-address").unwrap();
-        Arc::new(Mutex::new(client_ip.to_string()))
-    };
-    let client_port = {
-        let client_port = value_t!(matches, "client-port", u16).unwrap();
-        Arc::new(Mutex::new(client_port))
-    };
+/// Rules listening on this side and the reverse rules the client side is asked to listen on.
+struct Rules {
+    forwards: Forwards,
+    reverse: Vec<ForwardRule>,
+    tunnel_writer: Sender<WriterData>,
+}
 
-    let tunnel_reader = tunnel.reader;
-    let tunnel_writer = tunnel.writer;
+impl Rules {
+    fn update(&mut self, cfg: ForwardsConfig) {
+        self.forwards.update(cfg.forwards);
+        if self.reverse != cfg.reverse {
+            self.reverse = cfg.reverse;
+            self.tunnel_writer.send(WriterData::Listen(self.reverse.clone())).unwrap();
+        }
+    }
 
-    let client_state_sender = manage_clients(tunnel_reader);
+    /// The client side can ask only for the destinations of the reverse rules, or for any with
+    /// a socks reverse rule.
+    fn allows(&self, endpoint: &Endpoint) -> bool {
+        self.reverse.iter().any(|rule| match rule.mode {
+            ForwardMode::Direct => rule.endpoint() == *endpoint,
+            ForwardMode::Socks => true,
+            ForwardMode::Udp => false,
+        })
+    }
 
-    let client_ip_thread = client_ip.clone();
-    let client_port_thread = client_port.clone();
+    /// The client side can send datagrams only to the destinations of the udp reverse rules.
+    fn allows_datagram(&self, host: &str, port: u16) -> bool {
+        self.reverse.iter().any(|rule| rule.mode == ForwardMode::Udp && rule.host == host && rule.port == port)
+    }
+}
 
-    thread::spawn(move || {
-        let client_ip = client_ip_thread;
-        let client_port = client_port_thread;
-        TcpListener::bind(address)
-            .and_then(move|listener| {
-                info!("Waiting for connection, going to sleep.");
-                for (id, socket) in listener .incoming().enumerate() {
-                    info!("Got connection {}", id);
-                    let id = (id + 1) as u64;
-                    let tunnel_writer = tunnel_writer.clone();
-                    let client_state_sender = client_state_sender.clone();
-                    {
-                        let client_ip = client_ip.lock().unwrap();
-                        let client_port = client_port.lock().unwrap();
-
-                        let _ = tunnel_writer.send(WriterData::Connect(id, (*client_ip).clone(), *client_port)).unwrap();
-                    }
-                    socket
-                        .and_then(|socket| {
-                            run_connection( tunnel_writer, id, client_state_sender, socket);
-                            Ok(())
-                        })
-                        .unwrap();
+pub fn run(matches: &ArgMatches, tunnel: Tunnel) -> io::Result<()>{
+    let tunnel_reader = tunnel.reader;
+    let tunnel_writer = tunnel.writer;
+    let tunnel_connection = tunnel.connection;
 
-                    info!("Closing connection {}", id);
+    let client_state_sender = manage_clients(tunnel_reader, tunnel.peer.clone(), tunnel.restarted);
+    let flows = new_flows();
 
-                }
-                Ok(())
-            })
-            .unwrap();
+    let mut rules = Rules {
+        forwards: Forwards::new(0, tunnel_writer.clone(), client_state_sender.clone(), flows.clone(), tunnel.peer.clone()),
+        reverse: Vec::new(),
+        tunnel_writer: tunnel_writer.clone(),
+    };
+    rules.update(load_rules(matches)?);
+    let rules = Arc::new(Mutex::new(rules));
+    let flow_rules = rules.clone();
+    manage_flows(tunnel.datagram, tunnel_writer.clone(), flows.clone(), 0, move |host, port| flow_rules.lock().unwrap().allows_datagram(host, port));
+
+    // connections from the reverse rules, they are created on this side
+    let reverse_writer = tunnel_writer.clone();
+    let reverse_state_sender = client_state_sender.clone();
+    let reverse_rules = rules.clone();
+    thread::spawn(move || {
+        for (id, endpoint) in tunnel_connection.iter() {
+            if !reverse_rules.lock().unwrap().allows(&endpoint) {
+                warn!("[{}] Refused reverse connection to {}, no reverse rule goes there", id, endpoint);
+                reverse_writer.send(WriterData::Disconnect(id)).unwrap();
+                continue;
+            }
+            info!("Got reverse connection [{}] to {}", id, endpoint);
+            connect(reverse_writer.clone(), id, reverse_state_sender.clone(), &endpoint);
+        }
     });
 
-    prompt(client_ip, client_port);
+    // the signal can come while the prompt is waiting for the input, so the shutdown is done in
+    // its own thread, which ends the process
+    let shutdown_rules = rules.clone();
+    let closing = thread::spawn(move || {
+        shutdown::wait();
+        let mut rules = shutdown_rules.lock().unwrap();
+        rules.update(ForwardsConfig {
+            forwards: Vec::new(),
+            reverse: Vec::new(),
+        });
+        shutdown::close(&mut rules.forwards, &client_state_sender, &tunnel_writer);
+        info!("Server finished");
+        use std::process;
+        process::exit(0);
+    });
 
-    info!("Server finished");
+    prompt(matches, &rules);
 
+    shutdown::request();
+    let _ = closing.join();
     Ok(())
 }
 
-fn prompt(client_ip: Arc<Mutex<String>>, client_port: Arc<Mutex<u16>>) {
+fn prompt(args: &ArgMatches, rules: &Mutex<Rules>) {
     let mut rl = Editor::<()>::new();
     if let Err(_) = rl.load_history(".history.txt") {
         warn!("No previous history");
     }
 
-    loop {
+    while !shutdown::requested() {
         match rl.readline(">> ") {
             Ok(line) => {
-                let args: Vec<&str> = line.split(char::is_whitespace).collect();
+                let args_line: Vec<&str> = line.split(char::is_whitespace).collect();
                 let parser = create_parser();
-                match parser.get_matches_from_safe(args) {
+                match parser.get_matches_from_safe(args_line) {
                     Ok(matches) =>
                         match matches.subcommand_name() {
-                            Some("connect") => {
-                                let matches = matches.subcommand_matches("connect").unwrap();
-                                let mut client_ip = client_ip.lock().unwrap();
-                                let ip = matches.value_of("ip").unwrap();
-                                *client_ip = ip.to_owned();
-                                let mut client_port = client_port.lock().unwrap();
-                                let port = value_t!(matches, "port", u16).unwrap();
-                                *client_port = port;
+                            Some("reload") => {
+                                match load_rules(args) {
+                                    Ok(cfg) => rules.lock().unwrap().update(cfg),
+                                    Err(e) => println!("Failed to load the forward rules: {}", e),
+                                }
+                            },
+                            Some("forwards") => {
+                                let rules = rules.lock().unwrap();
+                                for rule in rules.forwards.rules() {
+                                    println!("{}: {} -> {}", rule.name, rule.bind, rule.destination());
+                                }
+                                for rule in &rules.reverse {
+                                    println!("{} (reverse): remote {} -> {}", rule.name, rule.bind, rule.destination());
+                                }
                             },
                             _ => {
                                 println!("Unknown command");
@@ -130,36 +189,20 @@ fn prompt(client_ip: Arc<Mutex<String>>, client_port: Arc<Mutex<u16>>) {
 
 }
 
-use std::str::{self};
-
-fn is_val<T: str::FromStr>(x: String) -> Result<(), String> {
-     x.parse::<T>()
-         .and_then(|_| Ok(()))
-         .or_else(|_| Err(format!("Cannot parse {}", x)))
-}
-
 use clap::{App};
 
 fn create_parser() -> App<'static, 'static> {
-    use clap::{Arg, AppSettings, SubCommand};
+    use clap::{AppSettings, SubCommand};
     App::new(env!("CARGO_PKG_NAME"))
         .version(crate_version!())
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .setting(AppSettings::NoBinaryName)
-        .subcommand(SubCommand::with_name("connect")
-                    .about("Connect to the addres.")
-                    .arg(Arg::with_name("ip")
-                         .help("ip address to connect to")
-                         .index(1)
-                         .required(true)
-                         )
-                    .arg(Arg::with_name("port")
-                         .help("port to connect to")
-                         .index(2)
-                         .required(true)
-                         .validator(is_val::<u16>)
-                         )
+        .subcommand(SubCommand::with_name("reload")
+                    .about("Reload the forward rules from the config file.")
+                    )
+        .subcommand(SubCommand::with_name("forwards")
+                    .about("List the forward rules.")
                     )
         //.arg(Arg::with_name("tunnel-file-name")
             //.help("Name of the files to use for transfer data: tunnel.in tunnel.out, this is just the name, not the extension.")
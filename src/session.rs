@@ -0,0 +1,175 @@
+/**
+ * File: src/session.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use serde_yaml;
+
+use storage::{Storage, Periodic};
+use tools::{hostname, now_secs};
+
+/// Seconds after which a lock which is not refreshed belongs to nobody.
+const LOCK_TIMEOUT: u64 = 60;
+
+/// How often the server refreshes its lock.
+const LOCK_REFRESH: u64 = 20;
+
+/// How long to wait before checking that nobody else has taken the lock at the same time.
+const LOCK_SETTLE: u64 = 2;
+
+/// Extensions of the objects of one tunnel.
+const EXTENSIONS: [&str; 3] = [".in", ".out", ".lock"];
+
+/// Lock of the session, it is owned by the server using the session.
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionLock {
+    owner: String,
+    updated: u64,
+}
+
+/// Names of the tunnel objects of one session.
+pub struct Session {
+    /// Base name of the objects, the session name is the directory.
+    pub base: String,
+}
+
+impl Session {
+    pub fn new(session: Option<&str>, file_name: &str) -> Session {
+        Session {
+            base: match session {
+                Some(session) => format!("{}/{}", session, file_name),
+                None => file_name.to_owned(),
+            },
+        }
+    }
+
+    /// Object written by the server and read by the client.
+    pub fn server_to_client(&self) -> String {
+        format!("{}.in", self.base)
+    }
+
+    /// Object written by the client and read by the server.
+    pub fn client_to_server(&self) -> String {
+        format!("{}.out", self.base)
+    }
+
+    pub fn lock_name(&self) -> String {
+        format!("{}.lock", self.base)
+    }
+}
+
+/// Identification of this process in the lock.
+fn owner() -> String {
+    use std::process;
+    format!("{}:{}", hostname(), process::id())
+}
+
+fn lock_content(owner: &str) -> Vec<u8> {
+    let lock = SessionLock {
+        owner: owner.to_owned(),
+        updated: now_secs(),
+    };
+    serde_yaml::to_string(&lock).unwrap().into_bytes()
+}
+
+fn read_lock(storage: &mut dyn Storage, name: &str) -> io::Result<Option<SessionLock>> {
+    storage.get(name)
+        .map(|body| body.and_then(|body| serde_yaml::from_slice(&body).ok()))
+}
+
+/// Take the session for this server, it fails if another server is using the session. Must be
+/// called before the server removes the old tunnel objects.
+pub fn acquire(storage: &mut dyn Storage, session: &Session) -> io::Result<()> {
+    let name = session.lock_name();
+    let owner = owner();
+    if let Some(lock) = read_lock(storage, &name)? {
+        let age = now_secs().saturating_sub(lock.updated);
+        if lock.owner != owner && age < LOCK_TIMEOUT {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                format!("session '{}' is used by {} (updated {}s ago)", session.base, lock.owner, age)));
+        }
+    }
+    storage.put(&name, &lock_content(&owner))?;
+    // another server could have written the lock at the same time, the last one wins
+    use std::thread::sleep;
+    sleep(Duration::from_secs(LOCK_SETTLE));
+    match read_lock(storage, &name)? {
+        Some(ref lock) if lock.owner == owner => {
+            info!("Session '{}' locked by {}", session.base, owner);
+            Ok(())
+        }
+        Some(lock) => Err(io::Error::new(io::ErrorKind::AddrInUse,
+                format!("session '{}' was taken by {}", session.base, lock.owner))),
+        None => Err(io::Error::new(io::ErrorKind::Other,
+                format!("lock of the session '{}' disappeared", session.base))),
+    }
+}
+
+/// Keeps the lock of the session fresh while the server is running.
+pub fn refresh_lock(session: &Session) -> Periodic {
+    let owner = owner();
+    Periodic {
+        name: session.lock_name(),
+        interval: Duration::from_secs(LOCK_REFRESH),
+        content: Box::new(move || lock_content(&owner)),
+    }
+}
+
+/// Names of the sessions with a server holding the lock, only the named sessions are found.
+pub fn active(storage: &mut dyn Storage, file_name: &str) -> io::Result<Vec<String>> {
+    let lock_suffix = format!("/{}.lock", file_name);
+    let mut sessions = Vec::new();
+    for object in storage.list("")? {
+        let object = object.name;
+        if !object.ends_with(&lock_suffix) {
+            continue;
+        }
+        let name = &object[..object.len() - lock_suffix.len()];
+        if name.is_empty() || name.contains('/') {
+            continue;
+        }
+        if let Some(lock) = read_lock(storage, &object)? {
+            if now_secs().saturating_sub(lock.updated) < LOCK_TIMEOUT {
+                sessions.push(name.to_owned());
+            }
+        }
+    }
+    Ok(sessions)
+}
+
+/// Print the sessions found in the storage with the owners of their locks.
+pub fn list(storage: &mut dyn Storage) -> io::Result<()> {
+    let mut sessions = BTreeMap::new();
+    for object in storage.list("")? {
+        let object = object.name;
+        let base = match EXTENSIONS.iter().find(|ext| object.ends_with(*ext)) {
+            Some(ext) => object[..object.len() - ext.len()].to_owned(),
+            None => continue,
+        };
+        sessions.entry(base).or_insert_with(Vec::new).push(object);
+    }
+    if sessions.is_empty() {
+        println!("No sessions");
+    }
+    for (base, objects) in sessions {
+        let status = match read_lock(storage, &format!("{}.lock", base))? {
+            Some(lock) => {
+                let age = now_secs().saturating_sub(lock.updated);
+                if age < LOCK_TIMEOUT {
+                    format!("active, server {} seen {}s ago", lock.owner, age)
+                } else {
+                    format!("stale, server {} seen {}s ago", lock.owner, age)
+                }
+            }
+            None => "no server".to_owned(),
+        };
+        println!("{:30} {} [{}]", base, status, objects.join(", "));
+    }
+    Ok(())
+}
@@ -0,0 +1,82 @@
+/**
+ * File: src/shutdown.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::time::Duration;
+use libc;
+
+use tunnel::{self, WriterData};
+use connection::ClientState;
+use forward::Forwards;
+
+/// How long to wait for every step of the shutdown.
+const SHUTDOWN_TIMEOUT: u64 = 10;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// Request the shutdown on SIGINT and SIGTERM.
+pub fn install() {
+    let handler = handle_signal as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handler as libc::sighandler_t);
+    }
+}
+
+/// Request the shutdown, e.g. when the prompt is finished.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Block until the shutdown is requested.
+pub fn wait() {
+    while !sleep(Duration::from_secs(1)) {}
+}
+
+/// Sleep for the duration, or less when the shutdown is requested. Returns true, if the shutdown
+/// is requested.
+pub fn sleep(duration: Duration) -> bool {
+    use std::thread;
+    use std::time::Instant;
+    let start = Instant::now();
+    while !requested() && start.elapsed() < duration {
+        thread::sleep(Duration::from_millis(100));
+    }
+    requested()
+}
+
+/// Stop listening, disconnect all the connections, wait until the other side receives everything
+/// and remove the tunnel objects.
+pub fn close(forwards: &mut Forwards, client_state_sender: &Sender<ClientState>, tunnel_writer: &Sender<WriterData>) {
+    let timeout = Duration::from_secs(SHUTDOWN_TIMEOUT);
+    info!("Shutting down");
+    forwards.update(Vec::new());
+
+    let (sender, receiver) = channel();
+    client_state_sender.send(ClientState::CloseAll(sender)).unwrap();
+    if receiver.recv_timeout(timeout).is_err() {
+        warn!("Not all the connections have been closed");
+    }
+
+    if !tunnel::flush(tunnel_writer, timeout) {
+        warn!("The other side has not received all the messages");
+    }
+    if !tunnel::close(tunnel_writer, timeout) {
+        warn!("Failed to remove the tunnel files");
+    }
+    info!("Shutdown finished");
+}
@@ -0,0 +1,132 @@
+/**
+ * File: src/socks.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self, Read, Write};
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+const REPLY_SUCCEEDED: u8 = 0;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 7;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// Maximum size of the http request header.
+const MAX_HEADER: usize = 8192;
+
+/// Read the proxy request from a new connection, answer it and return the requested destination.
+/// SOCKS5 and HTTP CONNECT requests are recognized by the first byte.
+/// The request is answered as successful before the other side of the tunnel connects, if that
+/// fails the connection is just closed.
+pub fn handshake<S: Read + Write>(socket: &mut S) -> io::Result<(String, u16)> {
+    let mut first = [0; 1];
+    socket.read_exact(&mut first)?;
+    if first[0] == SOCKS_VERSION {
+        socks5(socket)
+    } else {
+        http_connect(socket, first[0])
+    }
+}
+
+fn socks5<S: Read + Write>(socket: &mut S) -> io::Result<(String, u16)> {
+    // greeting: number of methods and the methods
+    let mut count = [0; 1];
+    socket.read_exact(&mut count)?;
+    let mut methods = vec![0; count[0] as usize];
+    socket.read_exact(&mut methods)?;
+    if !methods.contains(&NO_AUTHENTICATION) {
+        socket.write_all(&[SOCKS_VERSION, NO_ACCEPTABLE_METHOD])?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "socks client requires authentication"));
+    }
+    socket.write_all(&[SOCKS_VERSION, NO_AUTHENTICATION])?;
+
+    // request: version, command, reserved, address type
+    let mut request = [0; 4];
+    socket.read_exact(&mut request)?;
+    if request[0] != SOCKS_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown socks version {}", request[0])));
+    }
+    if request[1] != CMD_CONNECT {
+        reply(socket, REPLY_COMMAND_NOT_SUPPORTED)?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported socks command {}", request[1])));
+    }
+    let host = match request[3] {
+        ATYP_IPV4 => {
+            let mut ip = [0; 4];
+            socket.read_exact(&mut ip)?;
+            use std::net::Ipv4Addr;
+            Ipv4Addr::from(ip).to_string()
+        }
+        ATYP_DOMAIN => {
+            let mut len = [0; 1];
+            socket.read_exact(&mut len)?;
+            let mut domain = vec![0; len[0] as usize];
+            socket.read_exact(&mut domain)?;
+            io_res!(String::from_utf8(domain), InvalidData)?
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0; 16];
+            socket.read_exact(&mut ip)?;
+            use std::net::Ipv6Addr;
+            Ipv6Addr::from(ip).to_string()
+        }
+        atyp => {
+            reply(socket, REPLY_ADDRESS_NOT_SUPPORTED)?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported socks address type {}", atyp)));
+        }
+    };
+    let mut port = [0; 2];
+    socket.read_exact(&mut port)?;
+    let port = (port[0] as u16) << 8 | port[1] as u16;
+
+    reply(socket, REPLY_SUCCEEDED)?;
+    Ok((host, port))
+}
+
+fn reply<S: Write>(socket: &mut S, code: u8) -> io::Result<()> {
+    // the bound address is not known, because the connection is created on the other side
+    socket.write_all(&[SOCKS_VERSION, code, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+}
+
+fn http_connect<S: Read + Write>(socket: &mut S, first: u8) -> io::Result<(String, u16)> {
+    // read the whole header, byte by byte, so nothing after the header is lost
+    let mut header = vec![first];
+    let mut byte = [0; 1];
+    while !header.ends_with(b"\r\n\r\n") {
+        if header.len() > MAX_HEADER {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "http request header is too long"));
+        }
+        socket.read_exact(&mut byte)?;
+        header.push(byte[0]);
+    }
+    let header = io_res!(String::from_utf8(header), InvalidData)?;
+    let request = header.lines().next().unwrap_or("");
+    let parts = request.split_whitespace().collect::<Vec<_>>();
+    if parts.len() != 3 || parts[0] != "CONNECT" {
+        socket.write_all(b"HTTP/1.1 405 Method Not Allowed\r\n\r\n")?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported http request '{}'", request)));
+    }
+    let destination = parts[1];
+    let (host, port) = match destination.rfind(':') {
+        Some(pos) => (&destination[..pos], &destination[pos + 1..]),
+        None => (destination, "443"),
+    };
+    let host = host.trim_matches(&['[', ']'][..]);
+    let port = match port.parse::<u16>() {
+        Ok(port) => port,
+        Err(_) => {
+            socket.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n")?;
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid port in '{}'", destination)));
+        }
+    };
+    socket.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+    Ok((host.to_owned(), port))
+}
@@ -0,0 +1,73 @@
+/**
+ * File: src/split.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::collections::HashMap;
+use std::io::{self};
+
+use config::SplitConfig;
+use storage::{Storage, Object, Fetch};
+
+/// Storage with a tunnel api for each direction. Every side writes its files through its own
+/// tunnel api, e.g. to the bucket close to it, and reads the files of the other side through the
+/// tunnel api of the other side.
+pub struct SplitStorage {
+    own: Box<dyn Storage>,
+    other: Box<dyn Storage>,
+}
+
+impl Storage for SplitStorage {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.own.put(name, data)
+    }
+
+    /// The file is looked for in the own storage first, then in the storage of the other side.
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.own.get(name)? {
+            Some(data) => Ok(Some(data)),
+            None => self.other.get(name),
+        }
+    }
+
+    /// The file is removed from both storages, even when one of them fails.
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        let own = self.own.delete(name);
+        let other = self.other.delete(name);
+        own.and(other)
+    }
+
+    /// Files of both storages, with the latest modification time.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let mut objects: HashMap<String, Option<u64>> = HashMap::new();
+        for object in self.own.list(prefix)?.into_iter().chain(self.other.list(prefix)?) {
+            let modified = objects.entry(object.name).or_insert(object.modified);
+            *modified = (*modified).max(object.modified);
+        }
+        Ok(objects.into_iter()
+            .map(|(name, modified)| Object { name, modified })
+            .collect())
+    }
+
+    /// Only the tunnel file of the other side is watched.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        self.other.get_changed(name, version)
+    }
+}
+
+/// Create the storages of both tunnel apis, the client writes through the client one, every
+/// other mode through the server one.
+pub fn create_storage(cfg: SplitConfig, is_client: bool, create: fn(&str) -> io::Result<Box<dyn Storage>>) -> io::Result<Box<dyn Storage>> {
+    if [&cfg.server, &cfg.client].iter().any(|api| api.split(':').next() == Some("split")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "the split section needs the server and the client tunnel apis"));
+    }
+    let (own, other) = if is_client { (&cfg.client, &cfg.server) } else { (&cfg.server, &cfg.client) };
+    info!("Writing through {} tunnel api, reading through {} tunnel api", own, other);
+    Ok(Box::new(SplitStorage {
+        own: create(own)?,
+        other: create(other)?,
+    }))
+}
@@ -0,0 +1,222 @@
+/**
+ * File: src/sql.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self};
+use std::time::{Duration, Instant};
+
+use config::SqlConfig;
+use storage::{Storage, Object, Fetch};
+use tools::wait_with_input;
+use tools::now_secs;
+
+/// Removed files are kept this long as the empty rows, so the reader can see them.
+const TOMBSTONE_AGE: u64 = 3600;
+
+/// Database behind the sql storage, the sql commands are run by its command line client.
+enum Database {
+    Sqlite(String),
+    Postgres(String),
+}
+
+/// Storage in a table of a sql database. Every write is a new row with a bigger sequence number
+/// and the reader asks only for the rows newer than the last one it has read. Removing the file
+/// is a row without the data. Without the history only the last row of every file is kept, the
+/// older ones have been replaced, so the other side doesn't need them anymore.
+pub struct SqlStorage {
+    cfg: SqlConfig,
+    database: Database,
+    /// When the watched files have been checked last time.
+    checked: Vec<(String, Instant)>,
+}
+
+/// Quote the string for sql.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> io::Result<Vec<u8>> {
+    (0..hex.len() / 2)
+        .map(|i| io_res!(u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16), InvalidData))
+        .collect()
+}
+
+impl SqlStorage {
+    /// Run the sql, returns the rows of the output, the columns are separated by tabs.
+    fn sql(&self, sql: &str) -> io::Result<Vec<Vec<String>>> {
+        use std::process::{Command, Stdio};
+        let mut command = match self.database {
+            Database::Sqlite(ref path) => {
+                let mut command = Command::new("sqlite3");
+                command.args(["-batch", "-bail", "-noheader", "-separator", "\t", "-cmd", ".timeout 5000", path]);
+                command
+            }
+            Database::Postgres(ref url) => {
+                let mut command = Command::new("psql");
+                command.args(["-X", "-q", "-A", "-t", "-F", "\t", "-v", "ON_ERROR_STOP=1", url]);
+                command
+            }
+        };
+        // the sql goes through stdin, the data can be bigger than the command line allows
+        let child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let output = wait_with_input(child, Some(sql.as_bytes()))?;
+        if !output.status.success() {
+            return Err(io::Error::new(io::ErrorKind::Other, format!("sql failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| line.split('\t').map(|column| column.to_owned()).collect())
+            .collect())
+    }
+
+    fn hex_literal(&self, data: &[u8]) -> String {
+        match self.database {
+            Database::Sqlite(_) => format!("X'{}'", to_hex(data)),
+            Database::Postgres(_) => format!("decode('{}', 'hex')", to_hex(data)),
+        }
+    }
+
+    fn hex_column(&self) -> &'static str {
+        match self.database {
+            Database::Sqlite(_) => "hex(data)",
+            Database::Postgres(_) => "encode(data, 'hex')",
+        }
+    }
+
+    fn create_table(&self) -> io::Result<()> {
+        let table = &self.cfg.table;
+        let sql = match self.database {
+            // the reader and the writer are different processes, with wal they don't block each other
+            Database::Sqlite(_) => format!("PRAGMA journal_mode=WAL;
+                CREATE TABLE IF NOT EXISTS {0} (seq INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL, data BLOB, modified INTEGER NOT NULL);
+                CREATE INDEX IF NOT EXISTS {0}_name ON {0} (name, seq);", table),
+            // both sides start at the same time and create if not exists is not safe in parallel
+            Database::Postgres(_) => format!("BEGIN;
+                SELECT pg_advisory_xact_lock(hashtext('{0}'));
+                CREATE TABLE IF NOT EXISTS {0} (seq BIGSERIAL PRIMARY KEY, name TEXT NOT NULL, data BYTEA, modified BIGINT NOT NULL);
+                CREATE INDEX IF NOT EXISTS {0}_name ON {0} (name, seq);
+                COMMIT;", table),
+        };
+        self.sql(&sql).map(|_| ())
+    }
+
+    /// Add the row of the file, without the data the file is removed. Without the history the
+    /// older rows of the file and the old removed files are deleted.
+    fn insert(&mut self, name: &str, data: Option<&[u8]>) -> io::Result<()> {
+        let (table, now) = (&self.cfg.table, now_secs());
+        let data = data.map(|data| self.hex_literal(data)).unwrap_or_else(|| "NULL".to_owned());
+        // the reader must not see the file without any row
+        let mut sql = format!("BEGIN;\nINSERT INTO {} (name, data, modified) VALUES ({}, {}, {});\n", table, quote(name), data, now);
+        if !self.cfg.history {
+            sql.push_str(&format!("DELETE FROM {0} WHERE name = {1} AND seq < (SELECT max(seq) FROM {0} WHERE name = {1});\n", table, quote(name)));
+            sql.push_str(&format!("DELETE FROM {} WHERE data IS NULL AND modified < {};\n", table, now.saturating_sub(TOMBSTONE_AGE)));
+        }
+        sql.push_str("COMMIT;\n");
+        self.sql(&sql).map(|_| ())
+    }
+
+    /// The last row of the file newer than the sequence, returns the sequence and the data.
+    fn last_row(&self, name: &str, after: u64) -> io::Result<Option<(u64, Option<Vec<u8>>)>> {
+        let sql = format!("SELECT seq, CASE WHEN data IS NULL THEN 0 ELSE 1 END, {} FROM {} WHERE name = {} AND seq > {} ORDER BY seq DESC LIMIT 1;",
+                          self.hex_column(), self.cfg.table, quote(name), after);
+        match self.sql(&sql)?.into_iter().next() {
+            Some(row) => {
+                if row.len() < 2 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, format!("invalid row {:?}", row)));
+                }
+                let seq = io_res!(row[0].parse::<u64>(), InvalidData)?;
+                let data = if row[1] == "1" {
+                    Some(from_hex(row.get(2).map(|hex| hex.as_str()).unwrap_or(""))?)
+                } else {
+                    None
+                };
+                Ok(Some((seq, data)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Storage for SqlStorage {
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        self.insert(name, Some(data))
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        self.last_row(name, 0)
+            .map(|row| row.and_then(|(_, data)| data))
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        self.insert(name, None)
+    }
+
+    /// The files whose last row has the data.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let sql = format!("SELECT t.modified, t.name FROM {0} t JOIN (SELECT name, max(seq) AS seq FROM {0} GROUP BY name) l ON t.seq = l.seq
+                           WHERE t.data IS NOT NULL AND substr(t.name, 1, {1}) = {2};",
+                          self.cfg.table, prefix.chars().count(), quote(prefix));
+        Ok(self.sql(&sql)?
+            .into_iter()
+            .filter(|row| row.len() >= 2)
+            .map(|row| Object {
+                modified: row[0].parse().ok(),
+                name: row[1].clone(),
+            })
+            .collect())
+    }
+
+    /// The sequence of the last read row is the version, only the newer rows are read. The
+    /// database is asked at most once per the poll interval.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        let poll = Duration::from_millis(self.cfg.poll);
+        match self.checked.iter_mut().find(|&&mut (ref checked, _)| checked == name) {
+            Some(&mut (_, ref last)) if last.elapsed() < poll => return Ok(Fetch::Unchanged),
+            Some(&mut (_, ref mut last)) => *last = Instant::now(),
+            None => self.checked.push((name.to_owned(), Instant::now())),
+        }
+        let after = version.as_ref().and_then(|version| version.parse().ok()).unwrap_or(0);
+        match self.last_row(name, after)? {
+            None if version.is_some() => Ok(Fetch::Unchanged),
+            Some((seq, Some(data))) => {
+                *version = Some(seq.to_string());
+                Ok(Fetch::Changed(data))
+            }
+            _ => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+        }
+    }
+}
+
+pub fn create_storage(cfg: SqlConfig) -> io::Result<Box<dyn Storage>> {
+    let database = match (cfg.sqlite.clone(), cfg.postgres.clone()) {
+        (Some(path), None) => Database::Sqlite(path),
+        (None, Some(url)) => Database::Postgres(url),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "the sql section needs either sqlite or postgres")),
+    };
+    if cfg.table.is_empty() || !cfg.table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid table name '{}'", cfg.table)));
+    }
+    let storage = SqlStorage {
+        cfg,
+        database,
+        checked: Vec::new(),
+    };
+    storage.create_table()?;
+    Ok(Box::new(storage))
+}
@@ -0,0 +1,106 @@
+/**
+ * File: src/stdio.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use tunnel::{self, Tunnel, WriterData, ReaderData};
+use messages::Endpoint;
+use std::io::{self, Read, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+use clap::ArgMatches;
+use shutdown;
+
+/// Id of the only connection in the stdio mode.
+const CONNECTION_ID: u64 = 1;
+
+/// How long to wait for the other side to receive the last messages, before exiting.
+const FLUSH_TIMEOUT: u64 = 30;
+
+/// Pipe stdin and stdout through one tunnel connection, this is meant to be used as ssh
+/// ProxyCommand. It returns when either stdin is closed or the other side disconnects.
+pub fn run(matches: &ArgMatches, tunnel: Tunnel) -> io::Result<()> {
+    let ip = matches.value_of("client-address").unwrap().to_owned();
+    let port = value_t!(matches, "client-port", u16).unwrap();
+    let tunnel_reader = tunnel.reader;
+    let tunnel_writer = tunnel.writer;
+
+    info!("[{}] Connecting stdio to {}:{}", CONNECTION_ID, ip, port);
+    tunnel_writer.send(WriterData::Connect(CONNECTION_ID, Endpoint::Tcp(ip, port))).unwrap();
+
+    let (closed_sender, closed_receiver) = channel();
+    let stdin_writer = tunnel_writer.clone();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut stdin = stdin.lock();
+        let mut buf = [0; 2048];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0)   => break,
+                Ok(len) => stdin_writer.send(WriterData::Data(CONNECTION_ID, buf[0..len].to_vec())).unwrap(),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e)  => {
+                    error!("Failed to read stdin: {}", e);
+                    break;
+                }
+            }
+        }
+        info!("[{}] Stdin closed", CONNECTION_ID);
+        stdin_writer.send(WriterData::Disconnect(CONNECTION_ID)).unwrap();
+        let _ = closed_sender.send(());
+    });
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut peer_alive = false;
+    loop {
+        if closed_receiver.try_recv().is_ok() {
+            break;
+        }
+        if shutdown::requested() {
+            info!("[{}] Closing connection on the signal", CONNECTION_ID);
+            tunnel_writer.send(WriterData::Disconnect(CONNECTION_ID)).unwrap();
+            break;
+        }
+        let mut disconnect = false;
+        for (id, data) in tunnel_reader.try_iter() {
+            if id != CONNECTION_ID {
+                error!("Received a data from the tunnel for not existing client id: {}", id);
+                continue;
+            }
+            match data {
+                ReaderData::Disconnect => disconnect = true,
+                ReaderData::Data(data) => {
+                    stdout.write_all(&data)?;
+                    stdout.flush()?;
+                }
+            }
+        }
+        if disconnect {
+            info!("[{}] Disconnected by the other side", CONNECTION_ID);
+            break;
+        }
+        let alive = tunnel.peer.load(Ordering::SeqCst);
+        if peer_alive && !alive {
+            // there is nobody to acknowledge the last messages
+            error!("[{}] The other side is dead", CONNECTION_ID);
+            return Ok(());
+        }
+        peer_alive = alive;
+        wait_little!();
+    }
+
+    if !tunnel::flush(&tunnel_writer, Duration::from_secs(FLUSH_TIMEOUT)) {
+        warn!("The other side has not received all the messages");
+    }
+    if !tunnel::close(&tunnel_writer, Duration::from_secs(FLUSH_TIMEOUT)) {
+        warn!("Failed to remove the tunnel files");
+    }
+    info!("[{}] Closing connection", CONNECTION_ID);
+    Ok(())
+}
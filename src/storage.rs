@@ -0,0 +1,327 @@
+/**
+ * File: src/storage.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self};
+use std::sync::mpsc::{channel};
+use std::time::{Duration, Instant};
+use serde_yaml;
+
+use tunnel::*;
+use messages::*;
+use envelope::{self, Generation};
+
+/// Object storage used to pass the tunnel files, e.g. s3 bucket. Names of the objects are relative
+/// to the tunnel prefix of the storage and can contain '/'.
+pub trait Storage: Send {
+    /// Create or replace the object.
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()>;
+    /// Read the object, None if the object doesn't exist.
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>>;
+    /// Remove the object.
+    fn delete(&mut self, name: &str) -> io::Result<()>;
+    /// All the objects which start with the prefix.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>>;
+    /// Read the object only if it has changed since the version from the previous read, the
+    /// version is updated. By default the whole object is read and its content is compared, so
+    /// only the parsing is saved.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        match self.get(name)? {
+            Some(object) => {
+                let hash = format!("{:016x}", envelope::checksum(&object));
+                if version.as_ref() == Some(&hash) {
+                    Ok(Fetch::Unchanged)
+                } else {
+                    *version = Some(hash);
+                    Ok(Fetch::Changed(object))
+                }
+            }
+            None => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+        }
+    }
+}
+
+/// Result of reading the object, if it has changed.
+pub enum Fetch {
+    Unchanged,
+    Missing,
+    Changed(Vec<u8>),
+}
+
+/// Last state of an object watched by a thread of the storage, e.g. with a waiting read.
+pub enum Watched {
+    Missing,
+    /// The version and the content.
+    Object(String, Vec<u8>),
+    Failed(String),
+}
+
+impl Watched {
+    /// Result of get_changed for the state, the version is updated.
+    pub fn fetch(&self, version: &mut Option<String>) -> io::Result<Fetch> {
+        match *self {
+            Watched::Object(ref current, _) if version.as_ref() == Some(current) => Ok(Fetch::Unchanged),
+            Watched::Object(ref current, ref data) => {
+                *version = Some(current.clone());
+                Ok(Fetch::Changed(data.clone()))
+            }
+            Watched::Missing => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+            Watched::Failed(ref e) => Err(io::Error::new(io::ErrorKind::Other, e.clone())),
+        }
+    }
+}
+
+/// Object found in the storage.
+pub struct Object {
+    pub name: String,
+    /// Last modification time, seconds since the unix epoch, if the storage knows it.
+    pub modified: Option<u64>,
+}
+
+/// Object which is written again and again while the tunnel is running, e.g. session lock.
+pub struct Periodic {
+    /// Name of the object.
+    pub name: String,
+    /// How often to write the object.
+    pub interval: Duration,
+    /// Creates the content of the object every time it is written.
+    pub content: Box<dyn FnMut() -> Vec<u8> + Send>,
+}
+
+/// Create pipes for the tunnel, which are passed through the storage objects. The server side
+/// removes the old objects first. On close, the writer and the periodic objects are removed.
+pub fn create_pipes(is_server: bool, mut storage: Box<dyn Storage>, writer_name: &str, reader_name: &str, periodic: Vec<Periodic>) -> io::Result<TunnelPipes> {
+    let (writer_sender, writer_receiver) = channel::<WriteCommand>();
+    let (reader_sender, reader_receiver) = channel::<ReadCommand>();
+
+    info!("Reading data from '{}'", reader_name);
+    info!("Writing data to '{}'", writer_name);
+    // create thread own variables to avoid lifetime errors
+    let reader_name: String = reader_name.into();
+    let writer_name: String = writer_name.into();
+
+    if is_server {
+        for name in &[&reader_name, &writer_name] {
+            match storage.delete(name) {
+                Ok(_) => info!("Tunnel file {} is removed", name),
+                Err(e) => error!("Failed to remove {}: {}", name, e),
+            }
+        }
+    }
+
+    use std::thread;
+    thread::spawn(move|| {
+        let mut periodic = periodic
+            .into_iter()
+            .map(|object| (object, None))
+            .collect::<Vec<(Periodic, Option<Instant>)>>();
+        let mut generation = Generation::new();
+        let mut last_generation = None;
+        let mut reader_version = None;
+        let mut pending = None;
+        loop {
+            for cmd in writer_receiver.try_iter() {
+                match cmd {
+                    // only the last content matters, it has all the messages
+                    WriteCommand::Write(msg) => pending = Some(WriteCommand::Write(msg)),
+                    WriteCommand::Delete => pending = Some(WriteCommand::Delete),
+                    WriteCommand::Close(sender) => {
+                        let names = periodic.iter().map(|(object, _)| object.name.clone());
+                        for name in Some(writer_name.clone()).into_iter().chain(names) {
+                            match storage.delete(&name) {
+                                Ok(_) => info!("Tunnel file {} is removed", name),
+                                Err(e) => error!("Failed to remove {}: {}", name, e),
+                            }
+                        }
+                        let _ = sender.send(());
+                        return;
+                    }
+                }
+            }
+            // failed writes are tried again in the next round
+            pending = match pending.take() {
+                Some(WriteCommand::Write(msg)) => {
+                    match storage.put(&writer_name, &envelope::seal(&mut generation, &msg)) {
+                        Ok(_) => {
+                            info!("Message writes, size = {}", msg.len());
+                            None
+                        }
+                        Err(e) => {
+                            error!("Failed to write {}: {}", writer_name, e);
+                            Some(WriteCommand::Write(msg))
+                        }
+                    }
+                }
+                Some(WriteCommand::Delete) => {
+                    match storage.delete(&writer_name) {
+                        Ok(_) => {
+                            info!("Tunnel file {} is removed", writer_name);
+                            None
+                        }
+                        Err(e) => {
+                            error!("Failed to remove {}: {}", writer_name, e);
+                            Some(WriteCommand::Delete)
+                        }
+                    }
+                }
+                other => other,
+            };
+
+            // the tunnel can be already closed, then the close command comes in the next round
+            match storage.get_changed(&reader_name, &mut reader_version) {
+                Ok(Fetch::Unchanged) => (),
+                Ok(Fetch::Changed(object)) => {
+                    // broken or old objects are ignored, the next read gets the right one
+                    match envelope::open(&object) {
+                        Ok((Some(read), _)) if last_generation.map(|last| read < last).unwrap_or(false) => {
+                            warn!("Ignoring older version {} of {}", read, reader_name);
+                            reader_version = None;
+                        }
+                        Ok((read, body)) => {
+                            match serde_yaml::from_slice::<Vec<Message>>(body) {
+                                Ok(stream) => {
+                                    if read.is_some() {
+                                        last_generation = read;
+                                    }
+                                    let _ = reader_sender.send(ReadCommand::Read(stream));
+                                }
+                                Err(e) => {
+                                    warn!("Ignoring invalid {}: {}", reader_name, e);
+                                    reader_version = None;
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Ignoring broken {}: {}", reader_name, e);
+                            reader_version = None;
+                        }
+                    }
+                }
+                Ok(Fetch::Missing) => {
+                    // if the file is missing, just wait until it is created
+                    let _ = reader_sender.send(ReadCommand::NoFile);
+                }
+                Err(e) => {
+                    error!("Failed to read {}: {}", reader_name, e);
+                    let _ = reader_sender.send(ReadCommand::NoFile);
+                }
+            }
+
+            for &mut (ref mut object, ref mut last) in periodic.iter_mut() {
+                let due = last.map(|last: Instant| last.elapsed() >= object.interval).unwrap_or(true);
+                if due {
+                    let content = (object.content)();
+                    if let Err(e) = storage.put(&object.name, &content) {
+                        error!("Failed to write {}: {}", object.name, e);
+                    }
+                    *last = Some(Instant::now());
+                }
+            }
+
+            wait_little!();
+        }
+    });
+
+    Ok(TunnelPipes{
+        writer: writer_sender,
+        reader: reader_receiver,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::Receiver;
+
+    /// Storage which returns the prepared objects one by one as the changed reader file, the
+    /// versions it is asked with are kept.
+    struct Script {
+        objects: Vec<Vec<u8>>,
+        versions: Arc<Mutex<Vec<Option<String>>>>,
+    }
+
+    fn script(objects: Vec<Vec<u8>>) -> (Script, Arc<Mutex<Vec<Option<String>>>>) {
+        let versions = Arc::new(Mutex::new(Vec::new()));
+        (Script { objects, versions: versions.clone() }, versions)
+    }
+
+    impl Storage for Script {
+        fn put(&mut self, _: &str, _: &[u8]) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn get(&mut self, _: &str) -> io::Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn delete(&mut self, _: &str) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn list(&mut self, _: &str) -> io::Result<Vec<Object>> {
+            Ok(Vec::new())
+        }
+
+        fn get_changed(&mut self, _: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+            if self.objects.is_empty() {
+                Ok(Fetch::Unchanged)
+            } else {
+                self.versions.lock().unwrap().push(version.clone());
+                *version = Some(self.versions.lock().unwrap().len().to_string());
+                Ok(Fetch::Changed(self.objects.remove(0)))
+            }
+        }
+    }
+
+    /// Tunnel file with one keepalive message with the id, in the envelope of the generation.
+    fn object(generation: u64, id: usize) -> Vec<u8> {
+        let body = serde_yaml::to_string(&vec![Message { id, payload: Payload::Keepalive(1) }]).unwrap().into_bytes();
+        let mut object = format!("# tunnel {} {} {:016x}\n", generation, body.len(), envelope::checksum(&body)).into_bytes();
+        object.extend_from_slice(&body);
+        object
+    }
+
+    fn read_id(reader: &Receiver<ReadCommand>) -> Option<usize> {
+        match reader.recv_timeout(Duration::from_secs(1)) {
+            Ok(ReadCommand::Read(stream)) => stream.first().map(|message| message.id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn older_and_broken_reads_are_ignored() {
+        let mut truncated = object(12, 4);
+        truncated.pop();
+        let (storage, versions) = script(vec![object(10, 1), object(5, 2), object(9, 3), truncated, object(11, 5)]);
+        let pipes = create_pipes(false, Box::new(storage), "tunnel.out", "tunnel.in", Vec::new()).unwrap();
+        assert_eq!(read_id(&pipes.reader), Some(1));
+        // the generations 5 and 9 are older than 10 and the generation 12 is broken
+        assert_eq!(read_id(&pipes.reader), Some(5));
+        assert_eq!(read_id(&pipes.reader), None);
+        // the ignored objects are read again, even if their version doesn't change
+        assert_eq!(*versions.lock().unwrap(), vec![None, Some("1".to_owned()), None, None, None]);
+        let (sender, closed) = channel();
+        pipes.writer.send(WriteCommand::Close(sender)).unwrap();
+        closed.recv_timeout(Duration::from_secs(1)).unwrap();
+    }
+
+    #[test]
+    fn legacy_reads_are_not_ordered() {
+        let legacy = serde_yaml::to_string(&vec![Message { id: 2, payload: Payload::Keepalive(1) }]).unwrap().into_bytes();
+        let (storage, _) = script(vec![object(10, 1), legacy]);
+        let pipes = create_pipes(false, Box::new(storage), "tunnel.out", "tunnel.in", Vec::new()).unwrap();
+        assert_eq!(read_id(&pipes.reader), Some(1));
+        assert_eq!(read_id(&pipes.reader), Some(2));
+    }
+}
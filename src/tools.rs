@@ -2,7 +2,7 @@
  * File: src/tools.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 07.09.2017
- * Last Modified Date: 07.09.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 
@@ -21,3 +21,106 @@ macro_rules! io_res {
     };
 }
 
+
+/// Name of this machine, used to identify the tunnel endpoints.
+pub fn hostname() -> String {
+    use std::fs::File;
+    use std::io::Read;
+    let mut name = String::new();
+    match File::open("/proc/sys/kernel/hostname").and_then(|mut file| file.read_to_string(&mut name)) {
+        Ok(_) if !name.trim().is_empty() => name.trim().to_owned(),
+        _ => "unknown".to_owned(),
+    }
+}
+
+/// Seconds since the unix epoch.
+pub fn now_secs() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Wait until the spawned child finishes and return its output. The input is written to its
+/// stdin by another thread while the output is read, so a child which writes a lot before it
+/// reads all its input doesn't block both of them. A failed write is an error only when the
+/// child has succeeded, otherwise its own error is in the output.
+pub fn wait_with_input(mut child: ::std::process::Child, input: Option<&[u8]>) -> ::std::io::Result<::std::process::Output> {
+    use std::io::Write;
+    use std::thread;
+    let writer = input.map(|input| {
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_vec();
+        // stdin is closed at the end of the thread, so the child knows the whole input is there
+        thread::spawn(move || stdin.write_all(&input))
+    });
+    let output = child.wait_with_output()?;
+    match writer.map(|writer| writer.join().unwrap()) {
+        Some(Err(e)) if output.status.success() => Err(e),
+        _ => Ok(output),
+    }
+}
+
+/// Parse the utc time in the format 'YYYY-MM-DD' 'HH:MM[:SS[.fff]]' to the seconds since the unix
+/// epoch.
+pub fn parse_time(date: &str, time: &str) -> Option<u64> {
+    let date = date.split('-').map(|n| n.parse::<i64>().ok()).collect::<Option<Vec<_>>>()?;
+    let time = time.split(':').map(|n| n.split('.').next().unwrap().parse::<i64>().ok()).collect::<Option<Vec<_>>>()?;
+    if date.len() != 3 || time.len() < 2 || time.len() > 3 {
+        return None;
+    }
+    let (year, month, day) = (date[0], date[1], date[2]);
+    // a garbage time would make the object look older or newer than it is
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || time.iter().any(|part| *part < 0)
+        || time[0] > 23 || time[1] > 59 || time.get(2).map(|sec| *sec > 60).unwrap_or(false) {
+        return None;
+    }
+    // days from the civil date, the year starts in march, so the leap day is the last one
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let secs = days * 86400 + time[0] * 3600 + time[1] * 60 + time.get(2).cloned().unwrap_or(0);
+    if secs < 0 { None } else { Some(secs as u64) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_times() {
+        assert_eq!(parse_time("1970-01-01", "00:00:00"), Some(0));
+        assert_eq!(parse_time("2026-10-19", "09:15:30"), Some(1792401330));
+        assert_eq!(parse_time("1999-12-31", "23:59:59"), Some(946684799));
+        assert_eq!(parse_time("2100-3-1", "12:00"), Some(4107585600));
+    }
+
+    #[test]
+    fn leap_day() {
+        assert_eq!(parse_time("2000-02-29", "00:00:00"), Some(951782400));
+        assert_eq!(parse_time("2000-03-01", "00:00:00"), Some(951782400 + 86400));
+    }
+
+    #[test]
+    fn fraction_of_seconds() {
+        assert_eq!(parse_time("2026-10-19", "09:15:30.999"), Some(1792401330));
+        assert_eq!(parse_time("2026-10-19", "09:15"), Some(1792401300));
+    }
+
+    #[test]
+    fn invalid_times() {
+        assert_eq!(parse_time("", ""), None);
+        assert_eq!(parse_time("2026-10", "09:15:30"), None);
+        assert_eq!(parse_time("2026-10-19", "09"), None);
+        assert_eq!(parse_time("2026-10-19", "09:15:30:00"), None);
+        assert_eq!(parse_time("2026-Oct-19", "09:15:30"), None);
+        assert_eq!(parse_time("2026-13-19", "09:15:30"), None);
+        assert_eq!(parse_time("2026-10-00", "09:15:30"), None);
+        assert_eq!(parse_time("2026-10-19", "24:00:00"), None);
+        assert_eq!(parse_time("2026-10-19", "09:60:00"), None);
+        assert_eq!(parse_time("2026-10-19", "09:-5:00"), None);
+        // before the epoch
+        assert_eq!(parse_time("1969-12-31", "23:59:59"), None);
+    }
+}
@@ -2,20 +2,44 @@
  * File: src/tunnel.rs
  * Author: Anicka Burova <anicka.burova@gmail.com>
  * Date: 08.09.2017
- * Last Modified Date: 11.10.2017
+ * Last Modified Date: 19.10.2026
  * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
  */
 
 use messages::*;
+use config::ForwardRule;
 use std::sync::mpsc::{Sender, Receiver};
 use std::io::{self};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-type NewConnection = (u64, String, u16);
+type NewConnection = (u64, Endpoint);
+pub type NewDatagram = (u64, String, u16, Vec<u8>);
 
 pub enum WriterData {
-    Connect(u64, String, u16),
+    Connect(u64, Endpoint),
     Disconnect(u64),
     Data(u64, Vec<u8>),
+    Listen(Vec<ForwardRule>),
+    Datagram(u64, String, u16, Vec<u8>),
+    /// Not a message, the sender is notified when the other side has received all the messages
+    /// sent before this one.
+    Flush(Sender<()>),
+    /// Not a message, the tunnel is stopped, its objects are removed and then the sender is
+    /// notified.
+    Close(Sender<()>),
+}
+
+/// Whether the other side of the tunnel is alive, it is false until the first message arrives.
+pub type PeerStatus = Arc<AtomicBool>;
+
+/// How often to send keepalives and how long to wait for the other side before it is considered
+/// dead.
+#[derive(Clone, Copy)]
+pub struct Keepalive {
+    pub interval: Duration,
+    pub peer_timeout: Duration,
 }
 
 pub enum ReaderData {
@@ -26,7 +50,14 @@ pub enum ReaderData {
 pub struct Tunnel {
     pub writer: Sender<WriterData>,
     pub reader: Receiver<(u64, ReaderData)>,
-    pub connection: Option<Receiver<NewConnection>>,
+    pub connection: Receiver<NewConnection>,
+    /// Rules the other side asks this side to listen on.
+    pub listen: Receiver<Vec<ForwardRule>>,
+    /// Udp datagrams from the other side.
+    pub datagram: Receiver<NewDatagram>,
+    pub peer: PeerStatus,
+    /// The other side has been restarted, the connections with its previous instance are gone.
+    pub restarted: Receiver<()>,
 }
 
 pub enum WriteCommand {
@@ -34,6 +65,8 @@ pub enum WriteCommand {
     Write(Vec<u8>),
     /// Delete the tunnel, because there are no more messages.
     Delete,
+    /// Remove all the objects written by this side, notify the sender and stop.
+    Close(Sender<()>),
 }
 
 pub enum ReadCommand {
@@ -46,43 +79,98 @@ pub struct TunnelPipes {
     pub reader: Receiver<ReadCommand>,
 }
 
-fn read_tunnel(reader_pipe: &Receiver<ReadCommand>, reader_sync: &mut usize, writer_sync: &mut usize, connection_sender: &Option<Sender<NewConnection>>, reader_sender: &Sender<(u64, ReaderData)>) {
+/// State of the other side, from the messages read from the tunnel.
+struct Peer {
+    /// Id of the last message read from the other side.
+    reader_sync: usize,
+    /// Epoch of the other side from its keepalives.
+    epoch: Option<u64>,
+    /// When the last new message was read.
+    last_seen: Option<Instant>,
+    /// The epoch has changed, the other side has been restarted.
+    restarted: bool,
+}
+
+/// Where the messages read from the tunnel go, the receivers are in the Tunnel.
+struct ReadSenders {
+    reader: Sender<(u64, ReaderData)>,
+    connection: Sender<NewConnection>,
+    listen: Sender<Vec<ForwardRule>>,
+    datagram: Sender<NewDatagram>,
+    restarted: Sender<()>,
+}
+
+/// Returns true if there are new messages, which needs to be acknowledged by sync.
+fn read_tunnel(reader_pipe: &Receiver<ReadCommand>, writer_sync: &mut usize, peer: &mut Peer, senders: &ReadSenders) -> bool {
+    let mut need_sync = false;
     for msg in reader_pipe.try_iter() {
         match msg {
             ReadCommand::NoFile => (),
             ReadCommand::Read(mut stream) => {
+                let epoch = stream
+                    .iter()
+                    .rev()
+                    .filter_map(|msg| match msg.payload { Payload::Keepalive(epoch) => Some(epoch), _ => None })
+                    .next();
+                if let Some(epoch) = epoch {
+                    if peer.epoch.map(|current| current != epoch).unwrap_or(false) {
+                        // the other side has been restarted, its messages start from the beginning
+                        // and it hasn't received any of ours, the connections are closed before
+                        // the new ones from this read are made
+                        warn!("The other side has been restarted");
+                        peer.reader_sync = 0;
+                        *writer_sync = 0;
+                        peer.restarted = true;
+                        let _ = senders.restarted.send(());
+                    }
+                    peer.epoch = Some(epoch);
+                }
                 for msg in stream.drain(..) {
-                    if *reader_sync >= msg.id {
+                    if peer.reader_sync >= msg.id {
                         // if id of the message has already been processed
                         continue;
                     }
-                    *reader_sync = msg.id;
+                    peer.reader_sync = msg.id;
+                    peer.last_seen = Some(Instant::now());
                     match msg.payload {
-                        Payload::Connect(id,ip,port) => {
+                        Payload::Sync(..) | Payload::Keepalive(..) => (),
+                        // syncs are not acknowledged, otherwise both sides would sync each
+                        // other forever
+                        _ => need_sync = true,
+                    }
+                    match msg.payload {
+                        Payload::Connect(id, endpoint) => {
                             // start a connection!
-                            info!("[{}] Got connection request from the tunnel to {}:{}", id, ip, port);
-                            match connection_sender.as_ref() {
-                                Some(ref connection_sender) => connection_sender.send((id,ip,port)).unwrap(),
-                                None => (),
-                            }
+                            info!("[{}] Got connection request from the tunnel to {}", id, endpoint);
+                            senders.connection.send((id, endpoint)).unwrap();
                         }
                         Payload::Disconnect(id) => {
                             info!("[{}] got disconnection request from the tunnel", id);
-                            reader_sender.send((id,ReaderData::Disconnect)).unwrap();
+                            senders.reader.send((id,ReaderData::Disconnect)).unwrap();
                         }
                         Payload::Data(id, data) => {
                             info!("[{}] Got data", id);
-                            reader_sender.send((id,ReaderData::Data(data))).unwrap();
+                            senders.reader.send((id,ReaderData::Data(data))).unwrap();
                         }
                         Payload::Sync(_id, last_msg) => {
                             info!("Got sync: {}", last_msg);
                             *writer_sync = last_msg;
                         }
+                        Payload::Listen(rules) => {
+                            info!("Got request to listen on {} rules", rules.len());
+                            senders.listen.send(rules).unwrap();
+                        }
+                        Payload::Datagram(id, ip, port, data) => {
+                            debug!("[{}] Got datagram", id);
+                            senders.datagram.send((id, ip, port, data)).unwrap();
+                        }
+                        Payload::Keepalive(_) => debug!("Got keepalive"),
                     }
                 }
             }
         }
     }
+    need_sync
 }
 
 fn tidy_up_msgs(last_writer_sync: usize, writer_sync: usize, all_msgs: &mut Vec<Message>) {
@@ -108,10 +196,14 @@ fn tidy_up_msgs(last_writer_sync: usize, writer_sync: usize, all_msgs: &mut Vec<
     }
 }
 
-fn resync_msg(last_reader_sync: usize, reader_sync: usize, remove_sync: &mut Option<usize>, msg_id: &mut usize, all_msgs: &mut Vec<Message>) -> bool {
-    if last_reader_sync < reader_sync && remove_sync.is_none() {
+fn resync_msg(need_sync: bool, reader_sync: usize, remove_sync: &mut Option<usize>, msg_id: &mut usize, all_msgs: &mut Vec<Message>) -> bool {
+    if need_sync {
         // We read another messages from the tunnel, let the other side know, we
         // have them and it doesn't need to send them anymore.
+        if let Some(old_sync) = remove_sync.take() {
+            // the new sync replaces the previous one, if it is still waiting in the tunnel
+            all_msgs.retain(|msg| msg.id != old_sync);
+        }
         let id = *msg_id;
         *msg_id += 1;
         all_msgs
@@ -129,41 +221,131 @@ fn resync_msg(last_reader_sync: usize, reader_sync: usize, remove_sync: &mut Opt
     }
 }
 
-fn add_msgs(is_change: bool,  writer_receiver: &Receiver<WriterData>, all_msgs: &mut Vec<Message>, remove_sync: &mut Option<usize>, msg_id: &mut usize) -> bool {
-    let saved_len = all_msgs.len();
-    all_msgs.extend(
-        writer_receiver
-        .try_iter()
-        .map(|msg| {
+/// The messages to the previous instance of the restarted other side are dropped. The new one
+/// doesn't know the rules it has been asked to listen on, the last ones are sent again.
+fn restart_msgs(peer: &mut Peer, listen: &Option<Vec<ForwardRule>>, msg_id: &mut usize, all_msgs: &mut Vec<Message>) -> bool {
+    if !peer.restarted {
+        return false;
+    }
+    peer.restarted = false;
+    all_msgs.clear();
+    match *listen {
+        Some(ref rules) if !rules.is_empty() => {
+            info!("Sending the {} listen rules again", rules.len());
             let id = *msg_id;
             *msg_id += 1;
-            match msg {
-                WriterData::Connect(connection, ip , port) =>
-                    Message {
-                     id,
-                     payload: Payload::Connect(connection, ip, port),
-                    },
-                WriterData::Disconnect(connection) =>
-                    Message {
-                        id,
-                        payload: Payload::Disconnect(connection),
-                    },
-                WriterData::Data(connection,data) =>
-                    Message {
-                     id,
-                     payload: Payload::Data(connection, data),
-                    },
+            all_msgs.push(Message {
+                id,
+                payload: Payload::Listen(rules.clone()),
+            });
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Send a new keepalive when it is time, it replaces the previous one, if it is still waiting in
+/// the tunnel.
+fn keepalive_msg(keepalive: &Keepalive, epoch: u64, last_keepalive: &mut Option<Instant>, remove_keepalive: &mut Option<usize>, msg_id: &mut usize, all_msgs: &mut Vec<Message>) -> bool {
+    let due = last_keepalive.map(|last| last.elapsed() >= keepalive.interval).unwrap_or(true);
+    if due {
+        if let Some(old) = remove_keepalive.take() {
+            all_msgs.retain(|msg| msg.id != old);
+        }
+        let id = *msg_id;
+        *msg_id += 1;
+        all_msgs.push(Message {
+            id,
+            payload: Payload::Keepalive(epoch),
+        });
+        *remove_keepalive = Some(id);
+        *last_keepalive = Some(Instant::now());
+    }
+    due
+}
+
+/// Update the status of the other side, it is dead when nothing has been read for the peer
+/// timeout.
+fn check_peer(keepalive: &Keepalive, peer: &Peer, status: &PeerStatus) {
+    let alive = peer.last_seen.map(|last| last.elapsed() < keepalive.peer_timeout).unwrap_or(false);
+    if alive != status.swap(alive, Ordering::SeqCst) {
+        if alive {
+            info!("The other side is alive");
+        } else {
+            warn!("The other side has not been seen for {}s, closing all connections", keepalive.peer_timeout.as_secs());
+        }
+    }
+}
+
+fn add_msgs(is_change: bool,  writer_receiver: &Receiver<WriterData>, all_msgs: &mut Vec<Message>, flushes: &mut Vec<(usize, Sender<()>)>, closing: &mut Option<Sender<()>>, listen: &mut Option<Vec<ForwardRule>>, msg_id: &mut usize) -> bool {
+    let saved_len = all_msgs.len();
+    for msg in writer_receiver.try_iter() {
+        let payload = match msg {
+            WriterData::Connect(connection, endpoint)  => Payload::Connect(connection, endpoint),
+            WriterData::Disconnect(connection)        => Payload::Disconnect(connection),
+            WriterData::Data(connection,data)         => Payload::Data(connection, data),
+            WriterData::Listen(rules)                 => {
+                *listen = Some(rules.clone());
+                Payload::Listen(rules)
             }
-        }));
-    if saved_len < all_msgs.len() {
-        *remove_sync = None;
-        true
-    } else {
-        is_change
+            WriterData::Datagram(flow, ip, port, data) => Payload::Datagram(flow, ip, port, data),
+            WriterData::Flush(sender) => {
+                // wait for the last message which has been sent so far, syncs are never
+                // acknowledged, so they are skipped
+                let last = all_msgs
+                    .iter()
+                    .rev()
+                    .find(|msg| match msg.payload { Payload::Sync(..) | Payload::Keepalive(..) => false, _ => true })
+                    .map(|msg| msg.id)
+                    .unwrap_or(0);
+                flushes.push((last, sender));
+                continue;
+            }
+            WriterData::Close(sender) => {
+                *closing = Some(sender);
+                continue;
+            }
+        };
+        let id = *msg_id;
+        *msg_id += 1;
+        all_msgs.push(Message {
+            id,
+            payload,
+        });
     }
+    saved_len < all_msgs.len() || is_change
+}
+
+/// Notify all the flushes, which messages has been received by the other side.
+fn notify_flushes(writer_sync: usize, flushes: &mut Vec<(usize, Sender<()>)>) {
+    flushes.retain(|&(id, ref sender)| {
+        if id <= writer_sync {
+            let _ = sender.send(());
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// Wait until the other side receives all the messages sent to the tunnel writer so far.
+/// Returns false, if it doesn't happen in the timeout.
+pub fn flush(writer: &Sender<WriterData>, timeout: Duration) -> bool {
+    use std::sync::mpsc::channel;
+    let (sender, receiver) = channel();
+    writer.send(WriterData::Flush(sender)).is_ok() && receiver.recv_timeout(timeout).is_ok()
+}
+
+/// Stop the tunnel and remove its objects, the messages which have not been received by the other
+/// side are lost, so flush should be called first. Returns false, if it doesn't happen in the
+/// timeout.
+pub fn close(writer: &Sender<WriterData>, timeout: Duration) -> bool {
+    use std::sync::mpsc::channel;
+    let (sender, receiver) = channel();
+    writer.send(WriterData::Close(sender)).is_ok() && receiver.recv_timeout(timeout).is_ok()
 }
 
-pub fn run(is_server: bool, pipes: TunnelPipes) -> io::Result<Tunnel> {
+pub fn run(pipes: TunnelPipes, keepalive: Keepalive) -> io::Result<Tunnel> {
     use std::sync::mpsc::{channel};
     use std::thread;
     use std::str;
@@ -171,37 +353,74 @@ pub fn run(is_server: bool, pipes: TunnelPipes) -> io::Result<Tunnel> {
 
     let (writer_sender, writer_receiver) = channel::<WriterData>();
     let (reader_sender, reader_receiver) = channel();
-    let (connection_sender, connection_receiver) = if !is_server {
-        let (sender, receiver) = channel();
-        (Some(sender), Some(receiver))
-    } else {
-        (None, None)
+    let (connection_sender, connection_receiver) = channel();
+    let (listen_sender, listen_receiver) = channel();
+    let (datagram_sender, datagram_receiver) = channel();
+    let (restarted_sender, restarted_receiver) = channel();
+    let senders = ReadSenders {
+        reader: reader_sender,
+        connection: connection_sender,
+        listen: listen_sender,
+        datagram: datagram_sender,
+        restarted: restarted_sender,
     };
     let writer_pipe = pipes.writer;
     let reader_pipe = pipes.reader;
+    let peer_status = Arc::new(AtomicBool::new(false));
+    let status = peer_status.clone();
+    // epoch of this instance, so the other side knows when this side has been restarted
+    let epoch = {
+        use tools::now_secs;
+        use std::process;
+        now_secs() << 20 ^ process::id() as u64
+    };
 
     let mut writer_created = false;
     let _ = thread::spawn(move|| {
         info!("Tunnel thread started");
-        let mut reader_sync = 0;
         let mut writer_sync = 0;
         // at the begining, remove tunnel files
         let mut all_msgs: Vec<Message> = Vec::new(); // all the messages which are writen to the tunnel writer.
         let mut msg_id = 1; // start messages from 1, so we can keep writer_sync from 0
         let mut remove_sync = None;
+        let mut flushes = Vec::new();
+        let mut peer = Peer {
+            reader_sync: 0,
+            epoch: None,
+            last_seen: None,
+            restarted: false,
+        };
+        let mut last_keepalive = None;
+        let mut remove_keepalive = None;
+        let mut closing = None;
+        // the last rules the other side has been asked to listen on
+        let mut listen = None;
         loop {
             let last_writer_sync = writer_sync;
-            let last_reader_sync = reader_sync;
             // Reading tunnel input
-            read_tunnel(&reader_pipe, &mut reader_sync, &mut writer_sync, &connection_sender, &reader_sender);
+            let need_sync = read_tunnel(&reader_pipe, &mut writer_sync, &mut peer, &senders);
+
+            check_peer(&keepalive, &peer, &status);
 
             tidy_up_msgs(last_writer_sync, writer_sync, &mut all_msgs);
 
-            let mut is_change = resync_msg(last_reader_sync, reader_sync, &mut remove_sync, &mut msg_id, &mut all_msgs);
+            let mut is_change = restart_msgs(&mut peer, &listen, &mut msg_id, &mut all_msgs);
+
+            is_change |= resync_msg(need_sync, peer.reader_sync, &mut remove_sync, &mut msg_id, &mut all_msgs);
+
+            is_change |= keepalive_msg(&keepalive, epoch, &mut last_keepalive, &mut remove_keepalive, &mut msg_id, &mut all_msgs);
 
             //let new_msgs = writer_receiver.try_iter().collect::<Vec<Vec<u8>>>();
 
-            is_change = add_msgs(is_change, &writer_receiver, &mut all_msgs, &mut remove_sync, &mut msg_id);
+            is_change = add_msgs(is_change, &writer_receiver, &mut all_msgs, &mut flushes, &mut closing, &mut listen, &mut msg_id);
+
+            if let Some(sender) = closing.take() {
+                info!("Closing the tunnel");
+                let _ = writer_pipe.send(WriteCommand::Close(sender));
+                break;
+            }
+
+            notify_flushes(writer_sync, &mut flushes);
 
             if is_change {
                 let msg = serde_yaml::to_string(&all_msgs).unwrap();
@@ -222,7 +441,68 @@ pub fn run(is_server: bool, pipes: TunnelPipes) -> io::Result<Tunnel> {
         writer: writer_sender,
         reader: reader_receiver,
         connection: connection_receiver,
+        listen: listen_receiver,
+        datagram: datagram_receiver,
+        peer: peer_status,
+        restarted: restarted_receiver,
     })
 
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    fn rule(name: &str) -> ForwardRule {
+        ForwardRule {
+            name: name.to_owned(),
+            bind: "127.0.0.1:8000".to_owned(),
+            mode: Default::default(),
+            host: "127.0.0.1".to_owned(),
+            port: 8000,
+            path: None,
+        }
+    }
+
+    #[test]
+    fn restart_of_other_side() {
+        let (pipe_sender, pipe) = channel();
+        let (reader, _reader) = channel();
+        let (connection, _connection) = channel();
+        let (listen_sender, _listen) = channel();
+        let (datagram, _datagram) = channel();
+        let (restarted_sender, restarted) = channel();
+        let senders = ReadSenders { reader, connection, listen: listen_sender, datagram, restarted: restarted_sender };
+        let (writer_sender, writer_receiver) = channel();
+        let mut peer = Peer { reader_sync: 0, epoch: None, last_seen: None, restarted: false };
+        let (mut writer_sync, mut msg_id, mut all_msgs) = (0, 1, Vec::new());
+        let (mut flushes, mut closing, mut listen) = (Vec::new(), None, None);
+        let read = |payload, id, writer_sync: &mut usize, peer: &mut Peer| {
+            pipe_sender.send(ReadCommand::Read(vec![Message { id, payload }])).unwrap();
+            read_tunnel(&pipe, writer_sync, peer, &senders);
+        };
+        writer_sender.send(WriterData::Listen(vec![rule("web")])).unwrap();
+        writer_sender.send(WriterData::Data(3, b"data".to_vec())).unwrap();
+        assert!(add_msgs(false, &writer_receiver, &mut all_msgs, &mut flushes, &mut closing, &mut listen, &mut msg_id));
+        read(Payload::Keepalive(1), 5, &mut writer_sync, &mut peer);
+        read(Payload::Sync(1, 1), 6, &mut writer_sync, &mut peer);
+        // the listen message is acknowledged
+        tidy_up_msgs(0, writer_sync, &mut all_msgs);
+        assert_eq!(all_msgs.len(), 1);
+        assert!(!restart_msgs(&mut peer, &listen, &mut msg_id, &mut all_msgs));
+        assert_eq!(restarted.try_iter().count(), 0);
+        // the other side starts again with the new epoch, the data for its previous instance is
+        // dropped and the rules are sent again
+        read(Payload::Keepalive(2), 1, &mut writer_sync, &mut peer);
+        assert_eq!((peer.reader_sync, writer_sync), (1, 0));
+        assert_eq!(restarted.try_iter().count(), 1);
+        assert!(restart_msgs(&mut peer, &listen, &mut msg_id, &mut all_msgs));
+        match all_msgs[..] {
+            [Message { id: 3, payload: Payload::Listen(ref rules) }] => assert_eq!(rules, &vec![rule("web")]),
+            _ => panic!("the rules are not sent again"),
+        }
+        assert!(!restart_msgs(&mut peer, &listen, &mut msg_id, &mut all_msgs));
+    }
+}
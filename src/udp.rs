@@ -0,0 +1,203 @@
+/**
+ * File: src/udp.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Sender, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::io::{self};
+
+use config::ForwardRule;
+use forward::REVERSE_ID_BASE;
+use tunnel::{WriterData, NewDatagram};
+
+/// Seconds without any datagram after which the flow is forgotten.
+const FLOW_TIMEOUT: u64 = 60;
+
+/// Maximum size of one datagram.
+const MAX_DATAGRAM: usize = 65536;
+
+/// Datagrams from the tunnel for the flows on this side, by the flow id.
+pub type Flows = Arc<Mutex<HashMap<u64, Sender<Vec<u8>>>>>;
+
+pub fn new_flows() -> Flows {
+    Arc::new(Mutex::new(HashMap::new()))
+}
+
+/// Route datagrams from the tunnel to their flows. A datagram for an unknown flow of the other
+/// side creates a new flow, which sends the datagrams to the address in the datagram, if it is
+/// allowed. The ids of this side start from `id_base`, its unknown flows have timed out.
+pub fn manage_flows<F>(datagrams: Receiver<NewDatagram>, tunnel_writer: Sender<WriterData>, flows: Flows, id_base: u64, allowed: F)
+    where F: Fn(&str, u16) -> bool + Send + 'static {
+    thread::spawn(move || {
+        for (id, host, port, data) in datagrams.iter() {
+            let known = flows.lock().unwrap().get(&id).cloned();
+            let sender = match known {
+                Some(sender) => sender,
+                None if id & REVERSE_ID_BASE == id_base => {
+                    debug!("[{}] Dropped the datagram of an unknown udp flow", id);
+                    continue;
+                }
+                None if !allowed(&host, port) => {
+                    warn!("[{}] Refused udp flow to {}:{}, no rule goes there", id, host, port);
+                    continue;
+                }
+                None => {
+                    let (sender, receiver) = channel();
+                    match connect_flow(id, &host, port, receiver, tunnel_writer.clone(), flows.clone()) {
+                        Ok(()) => {
+                            flows.lock().unwrap().insert(id, sender.clone());
+                            sender
+                        }
+                        Err(e) => {
+                            error!("[{}] Failed to create udp flow to {}:{}: {}", id, host, port, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            let _ = sender.send(data);
+        }
+    });
+}
+
+/// Flow created by the other side, it has its own socket connected to the destination.
+fn connect_flow(id: u64, host: &str, port: u16, datagrams: Receiver<Vec<u8>>, tunnel_writer: Sender<WriterData>, flows: Flows) -> io::Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect((host, port))?;
+    socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+    info!("[{}] New udp flow to {}:{}", id, host, port);
+    let host = host.to_owned();
+    thread::spawn(move || {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let mut last = Instant::now();
+        loop {
+            for data in datagrams.try_iter() {
+                last = Instant::now();
+                if let Err(e) = socket.send(&data) {
+                    error!("[{}] Failed to send the datagram to {}:{}: {}", id, host, port, e);
+                }
+            }
+            match socket.recv(&mut buf) {
+                Ok(len) => {
+                    last = Instant::now();
+                    tunnel_writer.send(WriterData::Datagram(id, host.clone(), port, buf[0..len].to_vec())).unwrap();
+                }
+                Err(ref e) if is_timeout(e) => (),
+                Err(e) => error!("[{}] Failed to receive a datagram from {}:{}: {}", id, host, port, e),
+            }
+            if last.elapsed() > Duration::from_secs(FLOW_TIMEOUT) {
+                break;
+            }
+        }
+        flows.lock().unwrap().remove(&id);
+        info!("[{}] Udp flow to {}:{} timed out", id, host, port);
+    });
+    Ok(())
+}
+
+/// One peer sending datagrams to the listening socket.
+struct Peer {
+    id: u64,
+    datagrams: Receiver<Vec<u8>>,
+    last: Instant,
+}
+
+/// Listen for datagrams of the udp forward rule, every peer address gets its own flow.
+pub fn listen(socket: UdpSocket, rule: Arc<Mutex<ForwardRule>>, running: Arc<AtomicBool>, id_base: u64, last_id: Arc<AtomicUsize>, tunnel_writer: Sender<WriterData>, flows: Flows) -> io::Result<thread::JoinHandle<()>> {
+    socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+    Ok(thread::spawn(move || {
+        let mut buf = vec![0; MAX_DATAGRAM];
+        let mut peers: HashMap<SocketAddr, Peer> = HashMap::new();
+        while running.load(Ordering::SeqCst) {
+            match socket.recv_from(&mut buf) {
+                Ok((len, address)) => {
+                    let rule = rule.lock().unwrap().clone();
+                    let peer = peers.entry(address).or_insert_with(|| {
+                        let id = id_base + (last_id.fetch_add(1, Ordering::SeqCst) + 1) as u64;
+                        info!("[{}] New udp flow from {} on '{}'", id, address, rule.name);
+                        let (sender, receiver) = channel();
+                        flows.lock().unwrap().insert(id, sender);
+                        Peer {
+                            id,
+                            datagrams: receiver,
+                            last: Instant::now(),
+                        }
+                    });
+                    peer.last = Instant::now();
+                    tunnel_writer.send(WriterData::Datagram(peer.id, rule.host, rule.port, buf[0..len].to_vec())).unwrap();
+                }
+                Err(ref e) if is_timeout(e) => (),
+                Err(e) => error!("Failed to receive a datagram: {}", e),
+            }
+            for (address, peer) in peers.iter_mut() {
+                for data in peer.datagrams.try_iter() {
+                    peer.last = Instant::now();
+                    if let Err(e) = socket.send_to(&data, address) {
+                        error!("[{}] Failed to send the datagram to {}: {}", peer.id, address, e);
+                    }
+                }
+            }
+            let expired = peers
+                .iter()
+                .filter(|&(_, peer)| peer.last.elapsed() > Duration::from_secs(FLOW_TIMEOUT))
+                .map(|(address, _)| *address)
+                .collect::<Vec<_>>();
+            for address in expired {
+                let peer = peers.remove(&address).unwrap();
+                flows.lock().unwrap().remove(&peer.id);
+                info!("[{}] Udp flow from {} timed out", peer.id, address);
+            }
+        }
+        let mut flows = flows.lock().unwrap();
+        for peer in peers.values() {
+            flows.remove(&peer.id);
+        }
+        info!("Forward rule '{}' stopped listening", rule.lock().unwrap().name);
+    }))
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flows_of_other_side_to_allowed_destinations() {
+        let destination = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = destination.local_addr().unwrap().port();
+        let (datagram_sender, datagrams) = channel();
+        let (tunnel_writer, written) = channel();
+        let flows = new_flows();
+        manage_flows(datagrams, tunnel_writer, flows.clone(), 0, move |host, allowed| host == "127.0.0.1" && allowed == port);
+        // a flow of this side, which has timed out, and a destination without any rule
+        datagram_sender.send((1, "127.0.0.1".to_owned(), port, b"own".to_vec())).unwrap();
+        datagram_sender.send((REVERSE_ID_BASE + 1, "127.0.0.1".to_owned(), port + 1, b"other".to_vec())).unwrap();
+        datagram_sender.send((REVERSE_ID_BASE + 2, "127.0.0.1".to_owned(), port, b"allowed".to_vec())).unwrap();
+        destination.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0; 100];
+        let (len, from) = destination.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"allowed");
+        assert_eq!(flows.lock().unwrap().keys().cloned().collect::<Vec<_>>(), vec![REVERSE_ID_BASE + 2]);
+        // the reply goes back through the tunnel
+        destination.send_to(b"reply", from).unwrap();
+        match written.recv_timeout(Duration::from_secs(5)).unwrap() {
+            WriterData::Datagram(id, _, _, data) => assert_eq!((id, data), (REVERSE_ID_BASE + 2, b"reply".to_vec())),
+            _ => panic!("the reply is not a datagram"),
+        }
+    }
+}
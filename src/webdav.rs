@@ -0,0 +1,417 @@
+/**
+ * File: src/webdav.rs
+ * Author: Anicka Burova <anicka.burova@gmail.com>
+ * Date: 19.10.2026
+ * Last Modified Date: 19.10.2026
+ * Last Modified By: Anicka Burova <anicka.burova@gmail.com>
+ */
+
+use std::io::{self, Read};
+use std::time::Duration;
+use hyper::Client;
+use hyper::method::Method;
+use hyper::status::StatusCode;
+use hyper::header::{Authorization, Basic, Headers};
+use hyper::net::HttpsConnector;
+use hyper_openssl::OpensslClient;
+
+use config::WebDavConfig;
+use storage::{Storage, Object, Fetch};
+use tools::parse_time;
+use envelope;
+
+/// Properties asked by the listing.
+const PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:"><d:prop><d:resourcetype/><d:getlastmodified/></d:prop></d:propfind>"#;
+
+/// Storage in a collection of a WebDAV server. The objects are files, the directories in their
+/// names are created when needed.
+pub struct WebDavStorage {
+    cfg: WebDavConfig,
+    client: Client,
+    /// Path of the collection, the listed names are relative to it.
+    base_path: String,
+}
+
+/// Escape the name for the url, '/' is kept.
+fn encode(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Unescape the name from the url, invalid escapes are kept as they are.
+fn decode(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let hex = |b: u8| (b as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            hex(bytes[i + 1]).and_then(|high| hex(bytes[i + 2]).map(|low| high << 4 | low))
+        } else {
+            None
+        };
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Path of the url, without the scheme and the host.
+fn url_path(url: &str) -> &str {
+    match url.find("://") {
+        Some(pos) => url[pos + 3..].find('/').map(|path| &url[pos + 3 + path..]).unwrap_or(""),
+        None => url,
+    }
+}
+
+/// Contents of the elements with the local name, the namespace prefix is ignored. Empty
+/// elements are there as empty strings.
+fn elements<'a>(xml: &'a str, local: &str) -> Vec<&'a str> {
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        let tag = &rest[..end];
+        let full = tag.split(|c: char| c.is_whitespace() || c == '/').next().unwrap_or("");
+        if tag.starts_with('/') || full.rsplit(':').next() != Some(local) {
+            continue;
+        }
+        rest = &rest[end + 1..];
+        if tag.ends_with('/') {
+            found.push("");
+        } else if let Some(close) = rest.find(&format!("</{}>", full)) {
+            found.push(&rest[..close]);
+        }
+    }
+    found
+}
+
+/// Time of the http date, e.g. `Mon, 19 Oct 2026 09:15:30 GMT`.
+fn parse_http_date(date: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let parts = date.split_whitespace().collect::<Vec<_>>();
+    if parts.len() < 5 {
+        return None;
+    }
+    let month = MONTHS.iter().position(|m| *m == parts[2])? + 1;
+    parse_time(&format!("{}-{}-{}", parts[3], month, parts[1]), parts[4])
+}
+
+impl WebDavStorage {
+    /// Send the request for the object, returns the status, the headers and the body.
+    fn request(&self, method: Method, name: &str, headers: Headers, body: Option<&[u8]>) -> io::Result<(StatusCode, Headers, Vec<u8>)> {
+        let url = format!("{}/{}", self.cfg.url.trim_end_matches('/'), encode(name));
+        let send = || {
+            let mut builder = self.client.request(method.clone(), &url).headers(headers.clone());
+            if let Some(ref username) = self.cfg.username {
+                builder = builder.header(Authorization(Basic { username: username.clone(), password: self.cfg.password.clone() }));
+            }
+            if let Some(body) = body {
+                builder = builder.body(body);
+            }
+            builder.send()
+        };
+        // the kept alive connection can be already closed by the server, all the requests can
+        // be repeated
+        let mut response = match send() {
+            Ok(response) => response,
+            Err(e) => {
+                info!("Repeating {} {}: {}", method, url, e);
+                io_res!(send())?
+            }
+        };
+        let mut data = Vec::new();
+        response.read_to_end(&mut data)?;
+        Ok((response.status, response.headers.clone(), data))
+    }
+
+    /// Create the directories of the name, the existing ones are skipped.
+    fn create_dirs(&self, name: &str) -> io::Result<()> {
+        let dirs = name.split('/').collect::<Vec<_>>();
+        for i in 1..dirs.len() {
+            let dir = format!("{}/", dirs[..i].join("/"));
+            match self.request(Method::Extension("MKCOL".to_owned()), &dir, Headers::new(), None)? {
+                (StatusCode::Created, _, _) | (StatusCode::MethodNotAllowed, _, _) => (),
+                (status, _, _) => return Err(io::Error::new(io::ErrorKind::Other, format!("MKCOL {} failed: {}", dir, status))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Objects in the directory and its subdirectories, which start with the prefix.
+    fn list_dir(&self, dir: &str, prefix: &str, objects: &mut Vec<Object>) -> io::Result<()> {
+        let mut headers = Headers::new();
+        headers.set_raw("Depth", vec![b"1".to_vec()]);
+        headers.set_raw("Content-Type", vec![b"application/xml".to_vec()]);
+        let (status, _, body) = self.request(Method::Extension("PROPFIND".to_owned()), dir, headers, Some(PROPFIND.as_bytes()))?;
+        match status {
+            StatusCode::MultiStatus => (),
+            StatusCode::NotFound => return Ok(()),
+            status => return Err(io::Error::new(io::ErrorKind::Other, format!("PROPFIND {} failed: {}", dir, status))),
+        }
+        let body = String::from_utf8_lossy(&body);
+        let base = format!("{}/", decode(&self.base_path).trim_end_matches('/'));
+        for response in elements(&body, "response") {
+            let href = match elements(response, "href").first() {
+                Some(href) => decode(url_path(href.trim())),
+                None => continue,
+            };
+            let name = match href.strip_prefix(&base) {
+                Some(name) => name.trim_end_matches('/').to_owned(),
+                None => continue,
+            };
+            // the directory itself is in the response too
+            if name.is_empty() || name == dir.trim_end_matches('/') {
+                continue;
+            }
+            if !elements(response, "collection").is_empty() {
+                let sub = format!("{}/", name);
+                if sub.starts_with(prefix) || prefix.starts_with(&sub) {
+                    self.list_dir(&sub, prefix, objects)?;
+                }
+            } else if name.starts_with(prefix) {
+                objects.push(Object {
+                    modified: elements(response, "getlastmodified").first().and_then(|date| parse_http_date(date)),
+                    name,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Storage for WebDavStorage {
+    /// The missing directory is reported as a conflict, then it is created and the file is
+    /// written again.
+    fn put(&mut self, name: &str, data: &[u8]) -> io::Result<()> {
+        let mut created = false;
+        loop {
+            match self.request(Method::Put, name, Headers::new(), Some(data))? {
+                (StatusCode::Ok, _, _) | (StatusCode::Created, _, _) | (StatusCode::NoContent, _, _) => return Ok(()),
+                (StatusCode::Conflict, _, _) | (StatusCode::NotFound, _, _) if !created => {
+                    self.create_dirs(name)?;
+                    created = true;
+                }
+                (status, _, _) => return Err(io::Error::new(io::ErrorKind::Other, format!("PUT {} failed: {}", name, status))),
+            }
+        }
+    }
+
+    fn get(&mut self, name: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.request(Method::Get, name, Headers::new(), None)? {
+            (StatusCode::Ok, _, data) => Ok(Some(data)),
+            (StatusCode::NotFound, _, _) => Ok(None),
+            (status, _, _) => Err(io::Error::new(io::ErrorKind::Other, format!("GET {} failed: {}", name, status))),
+        }
+    }
+
+    fn delete(&mut self, name: &str) -> io::Result<()> {
+        match self.request(Method::Delete, name, Headers::new(), None)? {
+            (StatusCode::Ok, _, _) | (StatusCode::NoContent, _, _) | (StatusCode::NotFound, _, _) => Ok(()),
+            (status, _, _) => Err(io::Error::new(io::ErrorKind::Other, format!("DELETE {} failed: {}", name, status))),
+        }
+    }
+
+    /// The listing goes through the directories one by one, the servers usually don't allow
+    /// the infinite depth.
+    fn list(&mut self, prefix: &str) -> io::Result<Vec<Object>> {
+        let dir = &prefix[..prefix.rfind('/').map(|pos| pos + 1).unwrap_or(0)];
+        let mut objects = Vec::new();
+        self.list_dir(dir, prefix, &mut objects)?;
+        Ok(objects)
+    }
+
+    /// The file is read only if its ETag has changed, servers without ETag send it every time
+    /// and the content is compared.
+    fn get_changed(&mut self, name: &str, version: &mut Option<String>) -> io::Result<Fetch> {
+        let mut headers = Headers::new();
+        if let Some(ref etag) = *version {
+            headers.set_raw("If-None-Match", vec![etag.clone().into_bytes()]);
+        }
+        match self.request(Method::Get, name, headers, None)? {
+            (StatusCode::NotModified, _, _) => Ok(Fetch::Unchanged),
+            (StatusCode::Ok, headers, data) => {
+                let etag = headers.get_raw("ETag")
+                    .and_then(|etag| etag.first())
+                    .map(|etag| String::from_utf8_lossy(etag).into_owned());
+                let current = etag.unwrap_or_else(|| format!("{:016x}", envelope::checksum(&data)));
+                if version.as_ref() == Some(&current) {
+                    return Ok(Fetch::Unchanged);
+                }
+                *version = Some(current);
+                Ok(Fetch::Changed(data))
+            }
+            (StatusCode::NotFound, _, _) => {
+                *version = None;
+                Ok(Fetch::Missing)
+            }
+            (status, _, _) => Err(io::Error::new(io::ErrorKind::Other, format!("GET {} failed: {}", name, status))),
+        }
+    }
+}
+
+pub fn create_storage(cfg: WebDavConfig) -> io::Result<Box<dyn Storage>> {
+    let ssl = io_res!(OpensslClient::new())?;
+    let mut client = Client::with_connector(HttpsConnector::new(ssl));
+    client.set_read_timeout(Some(Duration::from_secs(30)));
+    Ok(Box::new(WebDavStorage {
+        base_path: url_path(&cfg.url).to_owned(),
+        cfg,
+        client,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// What the client has sent to the script, one request after another.
+    type Sent = Arc<Mutex<Vec<String>>>;
+
+    /// Server which answers every request with the next prepared response and closes the
+    /// connection.
+    fn script(responses: Vec<String>) -> (WebDavStorage, Sent) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dav/tunnel", listener.local_addr().unwrap());
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let requests = sent.clone();
+        thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if let Some(value) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    request.push_str(&line);
+                    if line == "\r\n" {
+                        break;
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                requests.lock().unwrap().push(request);
+                let (head, body) = response.split_once("\n\n").unwrap_or((&response, ""));
+                let response = format!("{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", head.replace('\n', "\r\n"), body.len(), body);
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        let mut client = Client::new();
+        client.set_read_timeout(Some(Duration::from_secs(5)));
+        let storage = WebDavStorage {
+            base_path: url_path(&url).to_owned(),
+            cfg: WebDavConfig {
+                url,
+                username: None,
+                password: None,
+            },
+            client,
+        };
+        (storage, sent)
+    }
+
+    fn multistatus(responses: &[(&str, &str)]) -> String {
+        let responses = responses.iter()
+            .map(|&(href, prop)| format!("<d:response><d:href>{}</d:href><d:propstat><d:prop>{}</d:prop></d:propstat></d:response>", href, prop))
+            .collect::<String>();
+        format!("HTTP/1.1 207 Multi-Status\n\n<?xml version=\"1.0\"?><d:multistatus xmlns:d=\"DAV:\">{}</d:multistatus>", responses)
+    }
+
+    #[test]
+    fn names_in_urls() {
+        assert_eq!(encode("s1/tunnel in/č%"), "s1/tunnel%20in/%C4%8D%25");
+        assert_eq!(decode(&encode("s1/tunnel in/č%")), "s1/tunnel in/č%");
+        assert_eq!(decode("%2f%2F"), "//");
+        // invalid escapes stay as they are
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz%4"), "%zz%4");
+        assert_eq!(decode("%čx"), "%čx");
+        assert_eq!(decode("%4č"), "%4č");
+        assert_eq!(url_path("https://example.com/dav/a%20b"), "/dav/a%20b");
+        assert_eq!(url_path("https://example.com"), "");
+        assert_eq!(url_path("/dav/a"), "/dav/a");
+    }
+
+    #[test]
+    fn elements_of_any_namespace() {
+        let xml = r#"<D:response xmlns:D="DAV:"><D:href>/a</D:href><D:resourcetype><D:collection/></D:resourcetype><href>/b</href><hrefs>/c</hrefs></D:response>"#;
+        assert_eq!(elements(xml, "href"), vec!["/a", "/b"]);
+        assert_eq!(elements(xml, "collection"), vec![""]);
+        assert_eq!(elements(xml, "response").len(), 1);
+        assert!(elements(xml, "getlastmodified").is_empty());
+        assert!(elements("<d:href>not closed", "href").is_empty());
+    }
+
+    #[test]
+    fn http_dates() {
+        assert_eq!(parse_http_date("Mon, 19 Oct 2026 09:15:30 GMT"), Some(1792401330));
+        assert_eq!(parse_http_date("Mon, 19 Okt 2026 09:15:30 GMT"), None);
+        assert_eq!(parse_http_date("19 Oct 2026"), None);
+    }
+
+    #[test]
+    fn listing_of_hrefs() {
+        let (mut storage, sent) = script(vec![
+            multistatus(&[
+                ("/dav/tunnel/s1/", "<d:resourcetype><d:collection/></d:resourcetype>"),
+                ("http://example.com/dav/tunnel/s1/tunnel%20in", "<d:getlastmodified>Mon, 19 Oct 2026 09:15:30 GMT</d:getlastmodified>"),
+                ("/dav/tunnel/s1/sub/", "<d:resourcetype><d:collection/></d:resourcetype>"),
+                ("/elsewhere/s1/x", ""),
+            ]),
+            multistatus(&[
+                ("/dav/tunnel/s1/sub/", "<d:resourcetype><d:collection/></d:resourcetype>"),
+                ("/dav/tunnel/s1/sub/%C4%8D", ""),
+            ]),
+        ]);
+        let objects = storage.list("s1/").unwrap();
+        assert_eq!(objects.iter().map(|o| (o.name.as_str(), o.modified)).collect::<Vec<_>>(),
+            vec![("s1/tunnel in", Some(1792401330)), ("s1/sub/č", None)]);
+        let sent = sent.lock().unwrap();
+        assert!(sent[0].starts_with("PROPFIND /dav/tunnel/s1/ "));
+        assert!(sent[1].starts_with("PROPFIND /dav/tunnel/s1/sub/ "));
+    }
+
+    #[test]
+    fn changes_by_etag() {
+        let (mut storage, sent) = script(vec![
+            "HTTP/1.1 200 OK\nETag: \"e1\"\n\ndata".to_owned(),
+            "HTTP/1.1 304 Not Modified".to_owned(),
+            "HTTP/1.1 404 Not Found".to_owned(),
+        ]);
+        let mut version = None;
+        assert!(matches!(storage.get_changed("s1/tunnel.in", &mut version).unwrap(), Fetch::Changed(ref data) if data == b"data"));
+        assert_eq!(version, Some("\"e1\"".to_owned()));
+        assert!(matches!(storage.get_changed("s1/tunnel.in", &mut version).unwrap(), Fetch::Unchanged));
+        assert!(matches!(storage.get_changed("s1/tunnel.in", &mut version).unwrap(), Fetch::Missing));
+        assert_eq!(version, None);
+        let sent = sent.lock().unwrap();
+        assert!(!sent[0].contains("If-None-Match"));
+        assert!(sent[1].contains("If-None-Match: \"e1\"\r\n"));
+        assert!(sent[2].contains("If-None-Match: \"e1\"\r\n"));
+    }
+}